@@ -14,10 +14,18 @@ pub use pallet::*;
 
 #[frame_support::pallet]
 pub mod pallet {
-    use frame_support::{dispatch::DispatchResultWithPostInfo, pallet_prelude::*};
+    use frame_support::{
+        dispatch::DispatchResultWithPostInfo,
+        pallet_prelude::*,
+        traits::{BalanceStatus, Currency, GetPalletVersion, PalletVersion, ReservableCurrency},
+    };
+    use frame_support::sp_runtime::traits::{Saturating, Zero};
     use frame_system::pallet_prelude::*;
     use sp_std::vec::Vec;
 
+    type BalanceOf<T> =
+        <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
     #[pallet::pallet]
     #[pallet::generate_store(pub(super) trait Store)]
     pub struct Pallet<T>(_);
@@ -25,6 +33,21 @@ pub mod pallet {
     #[pallet::config]
     pub trait Config: frame_system::Config {
         type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
+
+        /// The maximum length of a proof, in bytes.
+        #[pallet::constant]
+        type MaxProofLength: Get<u32>;
+
+        /// The currency in which claim deposits are reserved.
+        type Currency: ReservableCurrency<Self::AccountId>;
+
+        /// The base amount reserved for each claim.
+        #[pallet::constant]
+        type ClaimDepositBase: Get<BalanceOf<Self>>;
+
+        /// The amount reserved for each byte of a proof.
+        #[pallet::constant]
+        type ClaimDepositPerByte: Get<BalanceOf<Self>>;
     }
 
     #[pallet::event]
@@ -49,14 +72,27 @@ pub mod pallet {
         NotProofOwner,
 		/// Receiver of a transfer is sender themself
 		TransferToSelf,
+        /// The proof is longer than MaxProofLength.
+        ProofTooLong,
     }
 
+    /// owner, block of the claim or last transfer, and deposit reserved from the owner;
+    /// up to pallet version 3.0.0 there was no deposit, see `migrate_to_deposits`
     #[pallet::storage]
-    pub(super) type Proofs<T: Config> =
-        StorageMap<_, Blake2_128Concat, Vec<u8>, (T::AccountId, T::BlockNumber), ValueQuery>;
+    pub(super) type Proofs<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        Vec<u8>,
+        (T::AccountId, T::BlockNumber, BalanceOf<T>),
+        ValueQuery,
+    >;
 
     #[pallet::hooks]
-    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {}
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_runtime_upgrade() -> Weight {
+            Self::migrate_to_deposits()
+        }
+    }
 
     #[pallet::call]
     impl<T: Config> Pallet<T> {
@@ -67,14 +103,23 @@ pub mod pallet {
         ) -> DispatchResultWithPostInfo {
             let sender = ensure_signed(origin)?;
 
+            ensure!(
+                proof.len() <= T::MaxProofLength::get() as usize,
+                Error::<T>::ProofTooLong
+            );
+
             ensure!(
                 !Proofs::<T>::contains_key(&proof),
                 Error::<T>::ProofAlreadyClaimed
             );
 
+            let deposit = Self::claim_deposit(&proof);
+
+            T::Currency::reserve(&sender, deposit)?;
+
             let current_block = <frame_system::Module<T>>::block_number();
 
-            Proofs::<T>::insert(&proof, (&sender, current_block));
+            Proofs::<T>::insert(&proof, (&sender, current_block, deposit));
 
             Self::deposit_event(Event::ClaimCreated(sender, proof));
 
@@ -82,15 +127,22 @@ pub mod pallet {
         }
 
         #[pallet::weight(10_000)]
-        fn revoke_claim(origin: OriginFor<T>, proof: Vec<u8>) -> DispatchResultWithPostInfo {
+        pub(super) fn revoke_claim(origin: OriginFor<T>, proof: Vec<u8>) -> DispatchResultWithPostInfo {
             let sender = ensure_signed(origin)?;
 
+            ensure!(
+                proof.len() <= T::MaxProofLength::get() as usize,
+                Error::<T>::ProofTooLong
+            );
+
             ensure!(Proofs::<T>::contains_key(&proof), Error::<T>::NoSuchProof);
 
-            let (owner, _) = Proofs::<T>::get(&proof);
+            let (owner, _, deposit) = Proofs::<T>::get(&proof);
 
             ensure!(sender == owner, Error::<T>::NotProofOwner);
 
+            T::Currency::unreserve(&sender, deposit);
+
             Proofs::<T>::remove(&proof);
 
             Self::deposit_event(Event::ClaimRevoked(sender, proof));
@@ -99,23 +151,65 @@ pub mod pallet {
         }
 
         #[pallet::weight(10_000)]
-        fn transfer_claim(origin: OriginFor<T>, proof: Vec<u8>, to: T::AccountId) -> DispatchResultWithPostInfo {
+        pub(super) fn transfer_claim(origin: OriginFor<T>, proof: Vec<u8>, to: T::AccountId) -> DispatchResultWithPostInfo {
             let sender = ensure_signed(origin)?;
 
+            ensure!(
+                proof.len() <= T::MaxProofLength::get() as usize,
+                Error::<T>::ProofTooLong
+            );
+
 			ensure!(sender != to, Error::<T>::TransferToSelf);
             ensure!(Proofs::<T>::contains_key(&proof), Error::<T>::NoSuchProof);
 
-            let (owner, _) = Proofs::<T>::get(&proof);
+            let (owner, _, deposit) = Proofs::<T>::get(&proof);
 
             ensure!(sender == owner, Error::<T>::NotProofOwner);
 
+            let moved = if T::Currency::total_balance(&to).is_zero() {
+                // an account that does not exist cannot hold a reserve: the sender gets the
+                // deposit back and the receiver owns the claim without one, as before deposits
+                T::Currency::unreserve(&sender, deposit);
+                Zero::zero()
+            } else {
+                // only what is still reserved moves, e.g. after a slash, and only that is owed back
+                let leftover = T::Currency::repatriate_reserved(&sender, &to, deposit, BalanceStatus::Reserved)?;
+                deposit.saturating_sub(leftover)
+            };
+
             let current_block = <frame_system::Module<T>>::block_number();
 
-            Proofs::<T>::insert(&proof, (&to, current_block));
+            Proofs::<T>::insert(&proof, (&to, current_block, moved));
 
             Self::deposit_event(Event::ClaimTransfered(sender, to, proof));
 
             Ok(().into())
         }
     }
+
+    impl<T: Config> Pallet<T> {
+        /// Give claims made before deposits, up to pallet version 3.0.0, a zero deposit:
+        /// nothing was reserved for them, so nothing is released when they are revoked.
+        fn migrate_to_deposits() -> Weight {
+            let before_deposits = Self::storage_version()
+                .map_or(true, |version| version < PalletVersion::new(3, 1, 0));
+            if !before_deposits {
+                return T::DbWeight::get().reads(1);
+            }
+
+            let mut claims: Weight = 0;
+            Proofs::<T>::translate::<(T::AccountId, T::BlockNumber), _>(|_, (owner, block)| {
+                claims += 1;
+                Some((owner, block, Zero::zero()))
+            });
+
+            T::DbWeight::get().reads_writes(claims + 1, claims)
+        }
+
+        /// The deposit reserved for claiming `proof`.
+        fn claim_deposit(proof: &[u8]) -> BalanceOf<T> {
+            let length: BalanceOf<T> = (proof.len() as u32).into();
+            T::ClaimDepositBase::get().saturating_add(T::ClaimDepositPerByte::get().saturating_mul(length))
+        }
+    }
 }
@@ -1,23 +1,314 @@
-use crate::{Error, mock::*};
-use frame_support::{assert_ok, assert_noop};
+use crate::{Error, mock::*, pallet::Proofs};
+use frame_support::{
+	assert_ok, assert_noop,
+	storage::unhashed,
+	traits::{GetPalletVersion, OnRuntimeUpgrade, PalletVersion, Currency, ReservableCurrency},
+};
+use sp_runtime::DispatchError;
+
+/// the last event deposited by the pallet
+fn last_event() -> Event {
+	System::events().pop().expect("an event was deposited").event
+}
+
+#[test]
+fn create_claim_works() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		System::set_block_number(5);
+
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		// owned by the sender since the current block, with base and per-byte deposit reserved
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 5, 12));
+		assert_eq!(Balances::reserved_balance(1), 12);
+		assert_eq!(Balances::free_balance(1), 88);
+		assert_eq!(last_event(), Event::pallet_poe(crate::Event::ClaimCreated(1, claim)));
+	});
+}
+
+#[test]
+fn create_claim_failed_when_claim_already_exists() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		// not even by the owner
+		assert_noop!(
+			PoeModule::create_claim(Origin::signed(1), claim.clone()),
+			Error::<Test>::ProofAlreadyClaimed
+		);
+		assert_noop!(
+			PoeModule::create_claim(Origin::signed(2), claim.clone()),
+			Error::<Test>::ProofAlreadyClaimed
+		);
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 1, 12));
+	});
+}
+
+#[test]
+fn create_claim_failed_when_deposit_cannot_be_reserved() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(
+			PoeModule::create_claim(Origin::signed(4), vec![0, 1]),
+			pallet_balances::Error::<Test>::InsufficientBalance
+		);
+	});
+}
+
+#[test]
+fn create_claim_failed_when_unsigned() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(
+			PoeModule::create_claim(Origin::none(), vec![0, 1]),
+			DispatchError::BadOrigin
+		);
+	});
+}
+
+#[test]
+fn revoke_claim_works() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(1), claim.clone()));
+
+		assert!(!Proofs::<Test>::contains_key(&claim));
+		assert_eq!(last_event(), Event::pallet_poe(crate::Event::ClaimRevoked(1, claim.clone())));
+
+		// the deposit is released
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(1), 100);
+
+		// can be claimed again
+		assert_ok!(PoeModule::create_claim(Origin::signed(2), claim.clone()));
+		assert_eq!(Proofs::<Test>::get(&claim), (2, 1, 12));
+	});
+}
+
+#[test]
+fn revoke_claim_failed_when_claim_does_not_exist() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(1), vec![0, 1]),
+			Error::<Test>::NoSuchProof
+		);
+	});
+}
 
 #[test]
-fn it_works_for_default_value() {
+fn revoke_claim_failed_when_not_owner() {
 	new_test_ext().execute_with(|| {
-		// Dispatch a signed extrinsic.
-		assert_ok!(TemplateModule::do_something(Origin::signed(1), 42));
-		// Read pallet storage and assert an expected result.
-		assert_eq!(TemplateModule::something(), Some(42));
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(2), claim.clone()),
+			Error::<Test>::NotProofOwner
+		);
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 1, 12));
 	});
 }
 
 #[test]
-fn correct_error_for_none_value() {
+fn revoke_claim_failed_when_unsigned() {
 	new_test_ext().execute_with(|| {
-		// Ensure the expected error is thrown when no value is present.
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
 		assert_noop!(
-			TemplateModule::cause_error(Origin::signed(1)),
-			Error::<Test>::NoneValue
+			PoeModule::revoke_claim(Origin::none(), claim),
+			DispatchError::BadOrigin
 		);
 	});
 }
+
+#[test]
+fn transfer_claim_works() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+		System::set_block_number(3);
+
+		assert_ok!(PoeModule::transfer_claim(Origin::signed(1), claim.clone(), 2));
+
+		// owned by the receiver since the transfer, the deposit moving with it
+		assert_eq!(Proofs::<Test>::get(&claim), (2, 3, 12));
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(1), 88);
+		assert_eq!(Balances::reserved_balance(2), 12);
+		assert_eq!(Balances::free_balance(2), 100);
+		assert_eq!(
+			last_event(),
+			Event::pallet_poe(crate::Event::ClaimTransfered(1, 2, claim.clone()))
+		);
+
+		// only the new owner may revoke it now
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(1), claim.clone()),
+			Error::<Test>::NotProofOwner
+		);
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(2), claim));
+		assert_eq!(Balances::reserved_balance(2), 0);
+		assert_eq!(Balances::free_balance(2), 112);
+	});
+}
+
+#[test]
+fn transfer_claim_moves_what_is_left_of_a_slashed_deposit() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+		// 5 of the 12 reserved are gone
+		let _ = Balances::slash_reserved(&1, 5);
+
+		assert_ok!(PoeModule::transfer_claim(Origin::signed(1), claim.clone(), 2));
+
+		// the receiver is only owed what moved
+		assert_eq!(Proofs::<Test>::get(&claim), (2, 1, 7));
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::reserved_balance(2), 7);
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(2), claim));
+		assert_eq!(Balances::reserved_balance(2), 0);
+		assert_eq!(Balances::free_balance(2), 107);
+	});
+}
+
+#[test]
+fn transfer_claim_to_an_account_without_balance_returns_the_deposit() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		// account 4 has no balance to hold a reserve
+		assert_ok!(PoeModule::transfer_claim(Origin::signed(1), claim.clone(), 4));
+
+		// the sender is paid back, the receiver owes nothing
+		assert_eq!(Proofs::<Test>::get(&claim), (4, 1, 0));
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(1), 100);
+		assert_eq!(Balances::total_balance(&4), 0);
+
+		// and revokes it without releasing anything
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(4), claim.clone()));
+		assert!(!Proofs::<Test>::contains_key(&claim));
+		assert_eq!(Balances::total_balance(&4), 0);
+	});
+}
+
+#[test]
+fn transfer_claim_failed_when_transfer_to_self() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		assert_noop!(
+			PoeModule::transfer_claim(Origin::signed(1), claim.clone(), 1),
+			Error::<Test>::TransferToSelf
+		);
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 1, 12));
+	});
+}
+
+#[test]
+fn transfer_claim_failed_when_claim_does_not_exist() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(
+			PoeModule::transfer_claim(Origin::signed(1), vec![0, 1], 2),
+			Error::<Test>::NoSuchProof
+		);
+	});
+}
+
+#[test]
+fn transfer_claim_failed_when_not_owner() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		// not even to the owner
+		assert_noop!(
+			PoeModule::transfer_claim(Origin::signed(2), claim.clone(), 3),
+			Error::<Test>::NotProofOwner
+		);
+		assert_noop!(
+			PoeModule::transfer_claim(Origin::signed(2), claim.clone(), 1),
+			Error::<Test>::NotProofOwner
+		);
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 1, 12));
+	});
+}
+
+#[test]
+fn transfer_claim_failed_when_unsigned() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		assert_noop!(
+			PoeModule::transfer_claim(Origin::none(), claim, 2),
+			DispatchError::BadOrigin
+		);
+	});
+}
+
+#[test]
+fn calls_failed_when_proof_too_long() {
+	new_test_ext().execute_with(|| {
+		// MaxProofLength is 8 in the mock
+		let claim = vec![0; 8];
+		let long_claim = vec![0; 9];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+
+		assert_noop!(
+			PoeModule::create_claim(Origin::signed(1), long_claim.clone()),
+			Error::<Test>::ProofTooLong
+		);
+		assert_noop!(
+			PoeModule::revoke_claim(Origin::signed(1), long_claim.clone()),
+			Error::<Test>::ProofTooLong
+		);
+		assert_noop!(
+			PoeModule::transfer_claim(Origin::signed(1), long_claim.clone(), 2),
+			Error::<Test>::ProofTooLong
+		);
+		assert!(!Proofs::<Test>::contains_key(&long_claim));
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 1, 18));
+	});
+}
+
+#[test]
+fn runtime_upgrade_gives_earlier_claims_no_deposit() {
+	new_test_ext().execute_with(|| {
+		// claimed before deposits, by an owner with nothing reserved
+		let claim = vec![0, 1];
+		unhashed::put(&Proofs::<Test>::hashed_key_for(&claim), &(1u64, 3u64));
+		PalletVersion::new(3, 0, 0)
+			.put_into_storage::<<Test as frame_system::Config>::PalletInfo, PoeModule>();
+
+		PoeModule::on_runtime_upgrade();
+
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 3, 0));
+		assert_eq!(PoeModule::storage_version(), Some(PoeModule::current_version()));
+
+		// revoked without touching balances
+		assert_ok!(PoeModule::revoke_claim(Origin::signed(1), claim));
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(1), 100);
+	});
+}
+
+#[test]
+fn runtime_upgrade_leaves_current_claims_alone() {
+	new_test_ext().execute_with(|| {
+		let claim = vec![0, 1];
+		assert_ok!(PoeModule::create_claim(Origin::signed(1), claim.clone()));
+		PoeModule::current_version()
+			.put_into_storage::<<Test as frame_system::Config>::PalletInfo, PoeModule>();
+
+		PoeModule::on_runtime_upgrade();
+
+		assert_eq!(Proofs::<Test>::get(&claim), (1, 1, 12));
+	});
+}
@@ -96,7 +96,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("node-template"),
 	impl_name: create_runtime_str!("node-template"),
 	authoring_version: 1,
-	spec_version: 100,
+	spec_version: 101,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
 	transaction_version: 1,
@@ -259,9 +259,19 @@ impl pallet_sudo::Config for Runtime {
 	type Call = Call;
 }
 
+parameter_types! {
+	pub const MaxProofLength: u32 = 256;
+	pub const ClaimDepositBase: Balance = 10_000;
+	pub const ClaimDepositPerByte: Balance = 100;
+}
+
 /// Configure the template pallet in pallets/template.
 impl poe::Config for Runtime {
 	type Event = Event;
+	type MaxProofLength = MaxProofLength;
+	type Currency = Balances;
+	type ClaimDepositBase = ClaimDepositBase;
+	type ClaimDepositPerByte = ClaimDepositPerByte;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
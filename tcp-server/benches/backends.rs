@@ -0,0 +1,101 @@
+// compare the epoll and io_uring backends under many connections:
+// cargo bench --features io-uring --bench backends
+
+// use criterion for benchmarking
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for socket address and sharing
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use substrate_course_task_2::runtime::{self, available_cores};
+use substrate_course_task_2::{Config, Server};
+
+/// connections open at the same time per iteration
+const CONNECTIONS: usize = 64;
+
+/// bytes sent through each connection per iteration
+const PAYLOAD_SIZE: usize = 256 * 1024;
+
+/// start a server with one thread of the given flavor on an ephemeral port
+fn start_server(flavor: &str) -> SocketAddr {
+    // parse options as the binary would, both backends copy through one buffer
+    let config = Config::parse_from([
+        "server",
+        "--quiet",
+        "--copy-mode",
+        "buffered",
+        "--runtime",
+        flavor,
+    ]);
+    let listener = config.socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // a single serving thread, so both backends get one core
+    let server = Arc::new(Server::new(config).unwrap());
+    runtime::serve_per_core(server, vec![listener], &available_cores()[..1]).unwrap();
+
+    address
+}
+
+/// send the payload through one connection and read it back
+async fn round_trip(address: SocketAddr, payload: Arc<Vec<u8>>) {
+    let client = TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = client.into_split();
+
+    // send and read back concurrently
+    let send = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut received = 0;
+    loop {
+        match reader.read(&mut buffer).await.unwrap() {
+            0 => break,
+            n => received += n,
+        }
+    }
+    send.await.unwrap();
+
+    assert_eq!(received, PAYLOAD_SIZE);
+}
+
+/// all connections at once
+async fn burst(address: SocketAddr, payload: Arc<Vec<u8>>) {
+    let connections: Vec<_> = (0..CONNECTIONS)
+        .map(|_| tokio::spawn(round_trip(address, payload.clone())))
+        .collect();
+    for connection in connections {
+        connection.await.unwrap();
+    }
+}
+
+fn backends(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let payload = Arc::new(vec![0x5au8; PAYLOAD_SIZE]);
+
+    let mut group = c.benchmark_group("backends");
+    group.throughput(Throughput::Bytes((CONNECTIONS * PAYLOAD_SIZE) as u64));
+    group.sample_size(20);
+
+    for (name, flavor) in [("epoll", "thread-per-core"), ("io_uring", "io-uring")] {
+        let address = start_server(flavor);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| runtime.block_on(burst(address, payload.clone())))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+// compare throughput of the copy modes: cargo bench --bench echo
+
+// use criterion for benchmarking
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for socket address and sharing
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use substrate_course_task_2::{serve, Config, Server};
+
+/// bytes sent through the server per iteration
+const PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// start a server on an ephemeral port with the given copy mode
+fn start_server(runtime: &Runtime, copy_mode: &str) -> SocketAddr {
+    // parse options as the binary would
+    let config = Config::parse_from(["server", "--quiet", "--copy-mode", copy_mode]);
+
+    runtime.block_on(async {
+        // let the OS pick a free port
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // serve in the background
+        tokio::spawn(serve(listener, Arc::new(Server::new(config).unwrap())));
+        address
+    })
+}
+
+/// send the payload through one connection and read it back
+async fn round_trip(address: SocketAddr, payload: Arc<Vec<u8>>) {
+    let client = TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = client.into_split();
+
+    // send and read back concurrently
+    let send = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut received = 0;
+    loop {
+        match reader.read(&mut buffer).await.unwrap() {
+            0 => break,
+            n => received += n,
+        }
+    }
+    send.await.unwrap();
+
+    assert_eq!(received, PAYLOAD_SIZE);
+}
+
+fn copy_modes(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let payload = Arc::new(vec![0x5au8; PAYLOAD_SIZE]);
+
+    let mut group = c.benchmark_group("echo");
+    group.throughput(Throughput::Bytes(PAYLOAD_SIZE as u64));
+    group.sample_size(20);
+
+    for copy_mode in ["loop", "buffered", "splice"] {
+        let address = start_server(&runtime, copy_mode);
+        group.bench_function(BenchmarkId::from_parameter(copy_mode), |b| {
+            b.iter(|| runtime.block_on(round_trip(address, payload.clone())))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, copy_modes);
+criterion_main!(benches);
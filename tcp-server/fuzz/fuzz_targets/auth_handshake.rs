@@ -0,0 +1,26 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+// types for timeouts
+use std::time::Duration;
+
+use substrate_course_task_2::auth::Auth;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    // whatever the client answers, the nonce is unguessable
+    let auth = Auth::new(
+        vec![b"secret".to_vec()],
+        Duration::from_secs(5),
+        Duration::from_secs(3600),
+    );
+    let answer = common::session(data, |mut stream| async move {
+        let ip = "127.0.0.1".parse().unwrap();
+        assert!(auth.handshake(&mut stream, ip).await.is_err());
+    });
+
+    assert!(answer.ends_with(b"DENIED\n"));
+});
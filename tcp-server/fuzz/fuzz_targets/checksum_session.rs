@@ -0,0 +1,32 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::checksum::{serve_connection, Algorithm, ChecksumMode};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    // frames and garbage, including lengths beyond the limit
+    let answer = common::session(data, |mut stream| async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        let _ = serve_connection(
+            &mut stream,
+            address,
+            Algorithm::Crc32,
+            ChecksumMode::Append,
+            true,
+        )
+        .await;
+    });
+
+    // whole frames only, each ending with the checksum of its payload
+    let mut rest = &answer[..];
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let (payload, checksum) = rest[4..4 + length].split_at(length - 4);
+        assert_eq!(Algorithm::Crc32.digest(payload), checksum);
+        rest = &rest[4 + length..];
+    }
+});
@@ -0,0 +1,41 @@
+// drive a protocol over an in-memory connection, as a client sending fuzzed bytes
+
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+// types for futures
+use std::future::Future;
+
+/// run `serve` on one end of a connection while `input` is sent and the write half
+/// shut down on the other end, returning what the server answered
+pub fn session<F, Fut>(input: &[u8], serve: F) -> Vec<u8>
+where
+    F: FnOnce(DuplexStream) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    // paused clock, sleeps and timeouts complete at once
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        // both ends of the connection
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (mut reader, mut writer) = tokio::io::split(client);
+
+        // the server may stop reading at any point, errors are expected
+        let send = async {
+            let _ = writer.write_all(input).await;
+            let _ = writer.shutdown().await;
+        };
+        let receive = async {
+            let mut answer = Vec::new();
+            let _ = reader.read_to_end(&mut answer).await;
+            answer
+        };
+
+        tokio::join!(serve(server), send, receive).2
+    })
+}
@@ -0,0 +1,25 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::compression::{negotiate, Algorithm};
+
+fuzz_target!(|data: &[u8]| {
+    // only the first byte is read
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut header = data;
+    let algorithm = runtime.block_on(negotiate(&mut header));
+
+    // known algorithms round-trip, the rest is refused
+    match (data.first(), algorithm) {
+        (Some(&byte), Ok(algorithm)) => {
+            assert_eq!(algorithm.byte(), byte);
+            assert_eq!(Algorithm::from_byte(byte), Some(algorithm));
+        }
+        (Some(&byte), Err(_)) => assert_eq!(Algorithm::from_byte(byte), None),
+        (None, result) => assert!(result.is_err()),
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::http::parse_chunk_size;
+
+fuzz_target!(|data: &[u8]| {
+    // only the part before extensions matters
+    let size = parse_chunk_size(data);
+    let bare = data.split(|&byte| byte == b';').next().unwrap_or_default();
+    assert_eq!(size, parse_chunk_size(bare));
+});
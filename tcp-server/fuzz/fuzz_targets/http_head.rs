@@ -0,0 +1,13 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::http::{parse_head, Head};
+
+fuzz_target!(|data: &[u8]| {
+    // a complete head never claims more bytes than it was given
+    if let Ok(Head::Complete(_, length)) = parse_head(data) {
+        assert!(length <= data.len());
+    }
+});
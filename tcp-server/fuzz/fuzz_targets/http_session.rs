@@ -0,0 +1,16 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::http::{serve_connection, HttpFormat};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    // any byte stream, split into requests or rejected with 400 or 413
+    common::session(data, |mut stream| async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        let _ = serve_connection(&mut stream, address, HttpFormat::Json, true).await;
+    });
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::command::{parse, Command};
+
+fuzz_target!(|data: &[u8]| {
+    // text is echoed untouched
+    if let Ok(Command::Text(text)) = parse(data) {
+        assert_eq!(text, data);
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::command::serve_connection;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    // lines, commands and garbage, with delays on a paused clock
+    let answer = common::session(data, |mut stream| async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        let _ = serve_connection(&mut stream, address, true).await;
+    });
+
+    // every answer is a line unless the input ended without a line ending
+    if data.ends_with(b"\n") && !answer.is_empty() {
+        assert!(answer.ends_with(b"\n"));
+    }
+});
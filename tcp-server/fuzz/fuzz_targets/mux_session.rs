@@ -0,0 +1,23 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+use substrate_course_task_2::mux::{serve_connection, Frame};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    // frames and garbage, with a window small enough to stall streams
+    let answer = common::session(data, |mut stream| async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        let _ = serve_connection(&mut stream, address, 64, 4, true).await;
+    });
+
+    // the server only ever sends whole, valid frames
+    let mut rest = &answer[..];
+    while !rest.is_empty() {
+        let (_, length) = Frame::parse(rest, 64).unwrap().unwrap();
+        rest = &rest[length..];
+    }
+});
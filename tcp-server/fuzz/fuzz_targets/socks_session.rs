@@ -0,0 +1,39 @@
+#![no_main]
+
+// use libfuzzer to generate inputs
+use libfuzzer_sys::fuzz_target;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for sharing
+use std::sync::OnceLock;
+
+use substrate_course_task_2::socks::serve_connection;
+use substrate_course_task_2::{Config, Server};
+
+mod common;
+
+/// one server for all inputs, requiring a password and never relaying
+fn server() -> &'static Server {
+    static SERVER: OnceLock<Server> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let config = Config::parse_from([
+            "server",
+            "--quiet",
+            "--protocol",
+            "socks5",
+            "--auth-key",
+            "key",
+        ]);
+        Server::new(config).unwrap()
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    // greetings, logins, requests and garbage, with delays on a paused clock
+    common::session(data, |mut stream| async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        let _ = serve_connection(&mut stream, address, server()).await;
+    });
+});
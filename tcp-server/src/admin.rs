@@ -0,0 +1,88 @@
+// administration interface: one command per line, each answer ending with OK or ERR
+//
+//   SESSIONS     open connections, one per line: id, transport, peer, bytes received and sent
+//   KILL <id>    end a connection, audited with reason "admin_kill"
+
+// use tokio for async runtime
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// use anyhow for error handling
+use anyhow::{Context, Result};
+
+// types for sharing
+use std::sync::Arc;
+
+// connection audit log
+use crate::audit::{AuditLog, Transport};
+
+/// answer one command line
+pub fn answer(line: &str, audit: &AuditLog) -> String {
+    // keywords are case-insensitive
+    let mut words = line.split_whitespace();
+    let keyword = words.next().map(str::to_ascii_uppercase);
+    let argument = words.next();
+    if words.next().is_some() {
+        return "ERR too many arguments\n".to_string();
+    }
+
+    match (keyword.as_deref(), argument) {
+        (Some("SESSIONS"), None) => {
+            let mut answer = String::new();
+            for entry in audit.open_sessions() {
+                let transport = match entry.transport {
+                    Transport::Tcp => "tcp",
+                    Transport::Quic => "quic",
+                };
+                answer.push_str(&format!(
+                    "{} {} {} {} {}\n",
+                    entry.id, transport, entry.peer, entry.bytes_received, entry.bytes_sent
+                ));
+            }
+            answer + "OK\n"
+        }
+        (Some("KILL"), Some(id)) => match id.parse() {
+            Ok(id) if audit.kill(id) => "OK\n".to_string(),
+            Ok(_) => "ERR no such connection\n".to_string(),
+            Err(_) => "ERR invalid connection id\n".to_string(),
+        },
+        _ => "ERR unknown command\n".to_string(),
+    }
+}
+
+/// answer the commands of one administrator until they hang up
+async fn handle_admin(socket: TcpStream, audit: &AuditLog) -> Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // one answer per line
+    while let Some(line) = lines.next_line().await.context("Failed to read command")? {
+        writer
+            .write_all(answer(&line, audit).as_bytes())
+            .await
+            .context("Failed to answer command")?;
+    }
+
+    Ok(())
+}
+
+/// accept administrators on a listener, spawning a new task for each one
+pub async fn serve(listener: TcpListener, audit: Arc<AuditLog>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, address)) => {
+                // share the log with the new task
+                let audit = audit.clone();
+                tokio::spawn(async move {
+                    println!("New admin connection from {:?}", address);
+                    if let Err(e) = handle_admin(socket, &audit).await {
+                        println!("Admin connection from {:?} failed: {:#}", address, e);
+                    }
+                });
+            }
+            Err(e) => {
+                println!("Failed to establish an admin connection: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,387 @@
+// append-only audit log of every connection, one JSON object per line
+//
+// a line is written when a connection closes, with why it closed and the bytes it moved;
+// connections still open when the server shuts down are written with reason "shutdown", and
+// those ended through the admin interface with reason "admin_kill"
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+
+// use serde to write lines
+use serde::{Deserialize, Serialize};
+
+// use anyhow for error handling
+use anyhow::{Context as _, Result};
+
+// types for files, I/O, addresses, sharing, polling and timestamps
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// how the client reached the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Tcp,
+    Quic,
+}
+
+/// why a connection ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// the client finished and the server answered everything
+    ClientEof,
+    /// the connection failed
+    Error,
+    /// the client did not answer in time
+    Timeout,
+    /// the connection went beyond what it may use, such as plugin fuel
+    Limit,
+    /// the server stopped while the connection was open
+    Shutdown,
+    /// an administrator ended the connection
+    AdminKill,
+}
+
+impl CloseReason {
+    /// reason for a connection that ended with `error`
+    pub fn of(error: &anyhow::Error) -> Self {
+        // the I/O error that ended the connection, if any
+        let kind = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(io::Error::kind);
+        match kind {
+            Some(io::ErrorKind::TimedOut) => CloseReason::Timeout,
+            Some(io::ErrorKind::QuotaExceeded) => CloseReason::Limit,
+            _ => CloseReason::Error,
+        }
+    }
+}
+
+/// one line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// connections are numbered from 1 in the order they are accepted, in each run
+    pub id: u64,
+    pub transport: Transport,
+    /// accepted and closed, in milliseconds since the Unix epoch
+    pub accepted: u64,
+    pub closed: u64,
+    pub peer: SocketAddr,
+    pub local: Option<SocketAddr>,
+    /// SHA-256 fingerprint of the client certificate, when TLS authenticated one
+    pub tls_identity: Option<String>,
+    pub reason: CloseReason,
+    /// what went wrong, for reasons other than client_eof
+    pub error: Option<String>,
+    /// bytes on the wire, in each direction
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+/// milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// the file being written
+struct Output {
+    file: File,
+    /// bytes in the file so far
+    size: u64,
+}
+
+/// audit log shared by all connections, rotated by size
+pub struct AuditLog {
+    path: PathBuf,
+    /// rotate before the file grows beyond this
+    max_size: Option<u64>,
+    /// rotated files kept next to the current one
+    keep: usize,
+    output: Mutex<Output>,
+    /// id of the last connection accepted
+    last_id: AtomicU64,
+    /// connections not written yet
+    open: Mutex<HashMap<u64, Arc<Session>>>,
+}
+
+impl AuditLog {
+    /// append to the log at `path`, creating it when missing
+    pub fn open(path: &Path, max_size: Option<u64>, keep: usize) -> Result<Self> {
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            output: Mutex::new(Self::append(path)?),
+            last_id: AtomicU64::new(0),
+            open: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// open a file for appending, keeping what is there
+    fn append(path: &Path) -> Result<Output> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        let size = file
+            .metadata()
+            .with_context(|| format!("Failed to read audit log {}", path.display()))?
+            .len();
+
+        Ok(Output { file, size })
+    }
+
+    /// name of the `n`th most recent rotated file
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    /// move the current file to `.1`, shifting older ones and dropping the oldest
+    fn rotate(&self, output: &mut Output) -> Result<()> {
+        // make room
+        for n in (1..=self.keep).rev() {
+            let from = if n == 1 {
+                self.path.clone()
+            } else {
+                self.rotated_path(n - 1)
+            };
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(n))
+                    .with_context(|| format!("Failed to rotate {}", from.display()))?;
+            }
+        }
+        // nothing kept at all
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path).ok();
+        }
+        // start over
+        *output = Self::append(&self.path)?;
+
+        Ok(())
+    }
+
+    /// append one entry, rotating first when it would not fit
+    fn write(&self, entry: &Entry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => return println!("Failed to encode audit entry: {}", e),
+        };
+        line.push(b'\n');
+        let mut output = self.output.lock().unwrap();
+
+        // keep at least one entry per file
+        let full = self
+            .max_size
+            .is_some_and(|max_size| output.size > 0 && output.size + line.len() as u64 > max_size);
+        if full {
+            if let Err(e) = self.rotate(&mut output) {
+                println!("{:#}", e);
+                return;
+            }
+        }
+
+        // one write per line, so readers never see half an entry
+        match output.file.write_all(&line) {
+            Ok(()) => output.size += line.len() as u64,
+            Err(e) => println!("Failed to write audit log {}: {}", self.path.display(), e),
+        }
+    }
+
+    /// start auditing a connection that was just accepted
+    pub fn session(
+        self: &Arc<Self>,
+        transport: Transport,
+        peer: SocketAddr,
+        local: Option<SocketAddr>,
+        tls_identity: Option<String>,
+    ) -> Arc<Session> {
+        let session = Arc::new(Session {
+            log: self.clone(),
+            id: self.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            transport,
+            accepted: now(),
+            peer,
+            local,
+            tls_identity,
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            kill: Notify::new(),
+        });
+        // written at shutdown if still open then
+        self.open
+            .lock()
+            .unwrap()
+            .insert(session.id, session.clone());
+
+        session
+    }
+
+    /// connections open now, oldest first
+    pub fn open_sessions(&self) -> Vec<Entry> {
+        let mut open: Vec<_> = self
+            .open
+            .lock()
+            .unwrap()
+            .values()
+            .map(|session| session.entry(CloseReason::AdminKill, None))
+            .collect();
+        open.sort_by_key(|entry| entry.id);
+        open
+    }
+
+    /// end the open connection `id`, writing its entry right away;
+    /// false when no such connection is open
+    pub fn kill(&self, id: u64) -> bool {
+        let session = match self.open.lock().unwrap().remove(&id) {
+            Some(session) => session,
+            None => return false,
+        };
+        self.write(&session.entry(CloseReason::AdminKill, None));
+        // the task serving it stops at its next step
+        session.kill.notify_one();
+        true
+    }
+
+    /// write every connection still open, as ended by the shutdown
+    pub fn shutdown(&self) {
+        let open: Vec<_> = self.open.lock().unwrap().drain().map(|(_, s)| s).collect();
+        for session in open {
+            self.write(&session.entry(CloseReason::Shutdown, None));
+        }
+    }
+}
+
+/// a connection being audited
+pub struct Session {
+    log: Arc<AuditLog>,
+    id: u64,
+    transport: Transport,
+    accepted: u64,
+    peer: SocketAddr,
+    local: Option<SocketAddr>,
+    tls_identity: Option<String>,
+    /// bytes on the wire so far
+    received: AtomicU64,
+    sent: AtomicU64,
+    /// notified when an administrator ends the connection
+    kill: Notify,
+}
+
+impl Session {
+    /// what is known about the connection now
+    fn entry(&self, reason: CloseReason, error: Option<String>) -> Entry {
+        Entry {
+            id: self.id,
+            transport: self.transport,
+            accepted: self.accepted,
+            closed: now(),
+            peer: self.peer,
+            local: self.local,
+            tls_identity: self.tls_identity.clone(),
+            reason,
+            error,
+            bytes_received: self.received.load(Ordering::Relaxed),
+            bytes_sent: self.sent.load(Ordering::Relaxed),
+        }
+    }
+
+    /// number of the connection in the log
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// wait until an administrator ends the connection, its entry written already
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+
+    /// write the entry of the connection once it is over
+    pub fn close(&self, reason: CloseReason, error: Option<String>) {
+        // already written by a shutdown
+        if self.log.open.lock().unwrap().remove(&self.id).is_none() {
+            return;
+        }
+        self.log.write(&self.entry(reason, error));
+    }
+
+    /// write the entry of a connection that ended with `result`
+    pub fn close_with<T>(&self, result: &Result<T>) {
+        match result {
+            Ok(_) => self.close(CloseReason::ClientEof, None),
+            Err(e) => self.close(CloseReason::of(e), Some(format!("{:#}", e))),
+        }
+    }
+
+    /// count the bytes going through `stream`
+    pub fn wrap<S>(self: &Arc<Self>, stream: S) -> Audited<S> {
+        Audited {
+            inner: stream,
+            session: self.clone(),
+        }
+    }
+}
+
+/// stream counting what is read from and written to it
+pub struct Audited<S> {
+    inner: S,
+    session: Arc<Session>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Audited<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // count what was added
+        let before = buf.filled().len();
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - before;
+        this.session.received.fetch_add(n as u64, Ordering::Relaxed);
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Audited<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // count only what was actually written
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.session.sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
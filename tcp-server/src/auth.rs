@@ -0,0 +1,265 @@
+// challenge-response authentication with pre-shared keys
+//
+// server: `AUTH <hex nonce>\n`
+// client: `<hex HMAC-SHA256(key, nonce)>\n`
+// server: `OK\n` or `DENIED\n`
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// use hmac and sha2 for the challenge response
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// use anyhow for error handling
+use anyhow::{bail, Context, Result};
+
+// standard library types
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// bytes of random challenge
+const NONCE_SIZE: usize = 32;
+
+/// longest accepted line during the handshake
+const MAX_LINE: usize = 256;
+
+/// most addresses whose failed attempts are remembered, the quietest are forgotten first
+const MAX_FAILING_ADDRESSES: usize = 64 * 1024;
+
+/// failed attempts of one address
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u64,
+    /// time of the latest one
+    last: Instant,
+}
+
+/// failed attempts by address, and when expired ones were last forgotten
+struct FailureLog {
+    by_address: HashMap<IpAddr, Failures>,
+    swept: Instant,
+}
+
+/// keys accepted from clients and recent failed attempts
+pub struct Auth {
+    keys: Vec<Vec<u8>>,
+    timeout: Duration,
+    /// failed attempts are forgotten after this long without another
+    failure_window: Duration,
+    failures: Mutex<FailureLog>,
+}
+
+impl Auth {
+    /// accept any of `keys`, giving clients `timeout` to answer and remembering failed
+    /// attempts for `failure_window`
+    pub fn new(keys: Vec<Vec<u8>>, timeout: Duration, failure_window: Duration) -> Self {
+        Auth {
+            keys,
+            timeout,
+            failure_window,
+            failures: Mutex::new(FailureLog {
+                by_address: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// read keys from a file with one token per line, ignoring blank lines and `#` comments
+    pub fn read_tokens(path: &Path) -> Result<Vec<Vec<u8>>> {
+        // whole file
+        let tokens = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tokens from {}", path.display()))?;
+
+        Ok(tokens
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.as_bytes().to_vec())
+            .collect())
+    }
+
+    /// recent failed attempts recorded for `ip`
+    pub fn failures(&self, ip: IpAddr) -> u64 {
+        match self.failures.lock().unwrap().by_address.get(&ip) {
+            Some(failures) if failures.last.elapsed() < self.failure_window => failures.count,
+            _ => 0,
+        }
+    }
+
+    /// addresses with failed attempts still remembered, expired or not
+    pub fn failing_addresses(&self) -> usize {
+        self.failures.lock().unwrap().by_address.len()
+    }
+
+    /// whether `key` is one of the keys, compared in constant time
+    pub(crate) fn accepts(&self, key: &[u8]) -> bool {
+        self.keys.iter().any(|candidate| {
+            candidate.len() == key.len()
+                && candidate
+                    .iter()
+                    .zip(key)
+                    .fold(0, |difference, (a, b)| difference | (a ^ b))
+                    == 0
+        })
+    }
+
+    /// record a failed attempt, returning the new count for `ip`
+    pub(crate) fn record_failure(&self, ip: IpAddr) -> u64 {
+        let now = Instant::now();
+        let window = self.failure_window;
+        let mut log = self.failures.lock().unwrap();
+
+        // forget addresses quiet for a whole window, at most once per window
+        if now.duration_since(log.swept) >= window {
+            log.by_address
+                .retain(|_, failures| now.duration_since(failures.last) < window);
+            log.swept = now;
+        }
+        // still too many, forget the quietest
+        if log.by_address.len() >= MAX_FAILING_ADDRESSES && !log.by_address.contains_key(&ip) {
+            let quietest = log
+                .by_address
+                .iter()
+                .min_by_key(|(_, failures)| failures.last)
+                .map(|(&ip, _)| ip);
+            if let Some(quietest) = quietest {
+                log.by_address.remove(&quietest);
+            }
+        }
+
+        // count up, starting over after a quiet window
+        let failures = log.by_address.entry(ip).or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        if now.duration_since(failures.last) >= window {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+
+        failures.count
+    }
+
+    /// challenge the client, failing when it does not answer correctly in time
+    pub async fn handshake<S>(&self, stream: &mut S, ip: IpAddr) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // fresh challenge for every connection
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+
+        // run the exchange under the timeout
+        let verdict = tokio::time::timeout(self.timeout, self.challenge(stream, &nonce))
+            .await
+            .unwrap_or_else(|_| {
+                let message = format!("no answer within {:?}", self.timeout);
+                Err(io::Error::new(io::ErrorKind::TimedOut, message).into())
+            });
+
+        // tell the client and count failures
+        match verdict {
+            Ok(()) => {
+                stream.write_all(b"OK\n").await?;
+                Ok(())
+            }
+            Err(e) => {
+                // the client may already be gone
+                let _ = stream.write_all(b"DENIED\n").await;
+                let failures = self.record_failure(ip);
+                // keep the cause, so timeouts can be told apart
+                Err(e.context(format!(
+                    "Authentication failed for {} ({} failed attempts)",
+                    ip, failures
+                )))
+            }
+        }
+    }
+
+    /// send the challenge and check the answer against every key
+    async fn challenge<S>(&self, stream: &mut S, nonce: &[u8]) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // send challenge
+        stream
+            .write_all(format!("AUTH {}\n", hex::encode(nonce)).as_bytes())
+            .await?;
+        // read answer
+        let answer = read_line(stream).await?;
+        let answer = hex::decode(answer.trim()).context("answer is not hex")?;
+
+        // any key will do
+        let accepted = self.keys.iter().any(|key| {
+            let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key size");
+            mac.update(nonce);
+            mac.verify_slice(&answer).is_ok()
+        });
+        if !accepted {
+            bail!("wrong answer");
+        }
+
+        Ok(())
+    }
+}
+
+/// answer to a challenge line such as `AUTH 00ff..`, as a client would send it
+pub fn answer(key: &[u8], challenge: &str) -> Result<String> {
+    // nonce from the challenge
+    let nonce = challenge
+        .trim()
+        .strip_prefix("AUTH ")
+        .context("not a challenge")?;
+    let nonce = hex::decode(nonce).context("challenge is not hex")?;
+
+    // sign it
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key size");
+    mac.update(&nonce);
+
+    Ok(format!("{}\n", hex::encode(mac.finalize().into_bytes())))
+}
+
+/// authenticate to a server with `key`, as a client
+pub async fn authenticate<S>(stream: &mut S, key: &[u8]) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // challenge from server
+    let challenge = read_line(stream).await?;
+    // our answer
+    stream
+        .write_all(answer(key, &challenge)?.as_bytes())
+        .await?;
+
+    // verdict
+    match read_line(stream).await?.trim() {
+        "OK" => Ok(()),
+        verdict => bail!("Authentication refused: {}", verdict),
+    }
+}
+
+/// read one line byte by byte, so nothing after it is consumed
+async fn read_line<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut line = Vec::new();
+
+    loop {
+        // one byte at a time
+        match stream.read_u8().await.context("connection closed")? {
+            b'\n' => break,
+            byte => line.push(byte),
+        }
+        // refuse endless lines
+        if line.len() > MAX_LINE {
+            bail!("line too long");
+        }
+    }
+
+    String::from_utf8(line).context("line is not UTF-8")
+}
@@ -0,0 +1,617 @@
+// traffic capture to pcap or pcapng files, readable by Wireshark
+//
+// the server only sees the byte streams, so TCP/IP headers are synthesized:
+// a three-way handshake when the connection starts, one segment per read or write
+// with consistent sequence numbers, FIN on each shutdown and RST when the server
+// drops a connection it did not shut down
+//
+// connections only hand records over to a writer thread, so no file is written,
+// and no rotation happens, while polling a connection
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// use anyhow for error handling
+use anyhow::{Context as _, Result};
+
+// use clap to read options from command line
+use clap::ValueEnum;
+
+// types for files, I/O, addresses, sharing, threads, polling and timestamps
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// link type of packets starting with an IP header
+const LINKTYPE_RAW: u16 = 101;
+
+/// longest packet kept
+const SNAPLEN: u32 = 256 * 1024;
+
+/// records waiting for the writer thread before packets are dropped
+const PENDING_RECORDS: usize = 4096;
+
+/// longest payload of a synthesized segment, so lengths fit IP headers
+const MAX_SEGMENT: usize = 32 * 1024;
+
+/// TCP flags
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// capture file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// classic libpcap format
+    Pcap,
+    /// pcap next generation
+    Pcapng,
+}
+
+impl Format {
+    /// header starting every file
+    fn header(self) -> Vec<u8> {
+        let mut header = Vec::new();
+        match self {
+            Format::Pcap => {
+                // magic for microsecond timestamps, version 2.4
+                header.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+                header.extend_from_slice(&2u16.to_le_bytes());
+                header.extend_from_slice(&4u16.to_le_bytes());
+                // time zone and accuracy, always zero
+                header.extend_from_slice(&[0; 8]);
+                header.extend_from_slice(&SNAPLEN.to_le_bytes());
+                header.extend_from_slice(&(LINKTYPE_RAW as u32).to_le_bytes());
+            }
+            Format::Pcapng => {
+                // section header: byte-order magic, version 1.0, unknown section length
+                let mut section = Vec::new();
+                section.extend_from_slice(&0x1a2b_3c4du32.to_le_bytes());
+                section.extend_from_slice(&1u16.to_le_bytes());
+                section.extend_from_slice(&0u16.to_le_bytes());
+                section.extend_from_slice(&(-1i64).to_le_bytes());
+                block(&mut header, 0x0a0d_0d0a, &section);
+                // a single interface, microsecond timestamps by default
+                let mut interface = Vec::new();
+                interface.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+                interface.extend_from_slice(&0u16.to_le_bytes());
+                interface.extend_from_slice(&SNAPLEN.to_le_bytes());
+                block(&mut header, 1, &interface);
+            }
+        }
+        header
+    }
+
+    /// record holding one packet
+    fn record(self, packet: &[u8]) -> Vec<u8> {
+        // microseconds since the Unix epoch
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let micros = now.as_micros() as u64;
+        let length = packet.len() as u32;
+
+        let mut record = Vec::with_capacity(packet.len() + 32);
+        match self {
+            Format::Pcap => {
+                record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+                record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+                record.extend_from_slice(&length.to_le_bytes());
+                record.extend_from_slice(&length.to_le_bytes());
+                record.extend_from_slice(packet);
+            }
+            Format::Pcapng => {
+                // enhanced packet block on interface 0
+                let mut body = Vec::with_capacity(packet.len() + 20);
+                body.extend_from_slice(&0u32.to_le_bytes());
+                body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+                body.extend_from_slice(&(micros as u32).to_le_bytes());
+                body.extend_from_slice(&length.to_le_bytes());
+                body.extend_from_slice(&length.to_le_bytes());
+                body.extend_from_slice(packet);
+                block(&mut record, 6, &body);
+            }
+        }
+        record
+    }
+}
+
+/// append a pcapng block, padding its body to 32 bits
+fn block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let padding = (4 - body.len() % 4) % 4;
+    let length = (12 + body.len() + padding) as u32;
+    out.extend_from_slice(&block_type.to_le_bytes());
+    out.extend_from_slice(&length.to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&[0; 3][..padding]);
+    out.extend_from_slice(&length.to_le_bytes());
+}
+
+/// the file being written
+struct Output {
+    file: File,
+    /// bytes in the file so far
+    size: u64,
+}
+
+/// what connections hand over to the writer thread
+enum Message {
+    /// a record to append
+    Record(Vec<u8>),
+    /// answer once everything before is written
+    Flush(mpsc::Sender<()>),
+}
+
+/// the writer thread's side of the capture, owning the file
+struct Writer {
+    path: PathBuf,
+    format: Format,
+    /// rotate before the file grows beyond this
+    max_size: Option<u64>,
+    /// rotated files kept next to the current one
+    keep: usize,
+    output: Output,
+    /// records connections could not hand over
+    dropped: Arc<AtomicU64>,
+}
+
+impl Writer {
+    /// create a file with its header
+    fn open(path: &Path, format: Format) -> Result<Output> {
+        let mut file = File::create(path)
+            .with_context(|| format!("Failed to create capture file {}", path.display()))?;
+        let header = format.header();
+        file.write_all(&header)
+            .with_context(|| format!("Failed to write capture file {}", path.display()))?;
+
+        Ok(Output {
+            file,
+            size: header.len() as u64,
+        })
+    }
+
+    /// write what arrives until every connection and the capture are gone
+    fn run(mut self, messages: Receiver<Message>) {
+        for message in messages {
+            match message {
+                Message::Record(record) => self.write(&record),
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+            // tell when the writer falls behind
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                println!(
+                    "Capture file {} fell behind, {} packets dropped",
+                    self.path.display(),
+                    dropped
+                );
+            }
+        }
+    }
+
+    /// move the current file to `.1`, shifting older ones and dropping the oldest
+    fn rotate(&mut self) -> Result<()> {
+        // make room
+        for n in (1..=self.keep).rev() {
+            let from = if n == 1 {
+                self.path.clone()
+            } else {
+                rotated_path(&self.path, n - 1)
+            };
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, n))
+                    .with_context(|| format!("Failed to rotate {}", from.display()))?;
+            }
+        }
+        // nothing kept at all
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path).ok();
+        }
+        // start over
+        self.output = Self::open(&self.path, self.format)?;
+
+        Ok(())
+    }
+
+    /// append one record, rotating first when it would not fit
+    fn write(&mut self, record: &[u8]) {
+        // keep at least one packet per file
+        let header_size = self.format.header().len() as u64;
+        let size = self.output.size;
+        let full = self
+            .max_size
+            .is_some_and(|max_size| size > header_size && size + record.len() as u64 > max_size);
+        if full {
+            if let Err(e) = self.rotate() {
+                println!("{:#}", e);
+                return;
+            }
+        }
+
+        // one write per packet, so readers never see half a record
+        match self.output.file.write_all(record) {
+            Ok(()) => self.output.size += record.len() as u64,
+            Err(e) => println!(
+                "Failed to write capture file {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// name of the `n`th most recent rotated file of `path`
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+}
+
+/// capture file shared by all connections, rotated by size
+pub struct Capture {
+    path: PathBuf,
+    format: Format,
+    /// records on their way to the writer thread
+    records: SyncSender<Message>,
+    /// records dropped because the writer thread fell behind
+    dropped: Arc<AtomicU64>,
+    /// identification field of synthesized IPv4 headers
+    ip_id: AtomicU16,
+}
+
+impl Capture {
+    /// start a new capture file at `path`, replacing any existing one
+    pub fn create(path: &Path, format: Format, max_size: Option<u64>, keep: usize) -> Result<Self> {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = Writer {
+            path: path.to_path_buf(),
+            format,
+            max_size,
+            keep,
+            output: Writer::open(path, format)?,
+            dropped: dropped.clone(),
+        };
+
+        // a single thread does all the file work
+        let (records, messages) = mpsc::sync_channel(PENDING_RECORDS);
+        std::thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || writer.run(messages))
+            .context("Failed to start the capture writer")?;
+
+        Ok(Capture {
+            path: path.to_path_buf(),
+            format,
+            records,
+            dropped,
+            ip_id: AtomicU16::new(0),
+        })
+    }
+
+    /// name of the `n`th most recent rotated file
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        rotated_path(&self.path, n)
+    }
+
+    /// hand one packet over to the writer thread, dropping it when the writer falls behind
+    fn write(&self, packet: &[u8]) {
+        let record = self.format.record(packet);
+        if let Err(TrySendError::Full(_)) = self.records.try_send(Message::Record(record)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// wait until every packet handed over so far is in the file
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.records.send(Message::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// capture the traffic of a new connection, starting with a handshake
+    pub fn connection<S>(
+        self: &Arc<Self>,
+        stream: S,
+        client: SocketAddr,
+        server: SocketAddr,
+    ) -> Captured<S> {
+        let mut flow = Flow {
+            capture: self.clone(),
+            client: normalize(client, server),
+            server: normalize(server, client),
+            client_seq: rand::random(),
+            server_seq: rand::random(),
+            client_fin: false,
+            server_fin: false,
+        };
+
+        // SYN, SYN-ACK, ACK
+        flow.client_segment(SYN, &[]);
+        flow.client_seq = flow.client_seq.wrapping_add(1);
+        flow.server_segment(SYN | ACK, &[]);
+        flow.server_seq = flow.server_seq.wrapping_add(1);
+        flow.client_segment(ACK, &[]);
+
+        Captured {
+            inner: stream,
+            flow,
+        }
+    }
+}
+
+/// use the same family on both ends, mapping IPv4 into IPv6 when they differ
+fn normalize(address: SocketAddr, other: SocketAddr) -> SocketAddr {
+    match (address.ip(), other.ip()) {
+        (IpAddr::V4(ip), IpAddr::V6(_)) => {
+            SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), address.port())
+        }
+        _ => address,
+    }
+}
+
+/// state of one captured connection
+struct Flow {
+    capture: Arc<Capture>,
+    client: SocketAddr,
+    server: SocketAddr,
+    /// next sequence number of each side
+    client_seq: u32,
+    server_seq: u32,
+    /// which sides have shut down
+    client_fin: bool,
+    server_fin: bool,
+}
+
+impl Flow {
+    /// segments from client to server, acknowledging everything the server sent
+    fn client_segment(&mut self, flags: u8, payload: &[u8]) {
+        let ack = if flags & ACK != 0 { self.server_seq } else { 0 };
+        let packet = packet(
+            self.client,
+            self.server,
+            self.client_seq,
+            ack,
+            flags,
+            payload,
+            &self.capture.ip_id,
+        );
+        self.capture.write(&packet);
+    }
+
+    /// segments from server to client, acknowledging everything the client sent
+    fn server_segment(&mut self, flags: u8, payload: &[u8]) {
+        let packet = packet(
+            self.server,
+            self.client,
+            self.server_seq,
+            self.client_seq,
+            flags,
+            payload,
+            &self.capture.ip_id,
+        );
+        self.capture.write(&packet);
+    }
+
+    /// bytes the server received
+    fn received(&mut self, data: &[u8]) {
+        for chunk in data.chunks(MAX_SEGMENT) {
+            self.client_segment(PSH | ACK, chunk);
+            self.client_seq = self.client_seq.wrapping_add(chunk.len() as u32);
+        }
+    }
+
+    /// bytes the server sent
+    fn sent(&mut self, data: &[u8]) {
+        for chunk in data.chunks(MAX_SEGMENT) {
+            self.server_segment(PSH | ACK, chunk);
+            self.server_seq = self.server_seq.wrapping_add(chunk.len() as u32);
+        }
+    }
+
+    /// the client shut down its write half
+    fn client_shutdown(&mut self) {
+        if !self.client_fin {
+            self.client_fin = true;
+            self.client_segment(FIN | ACK, &[]);
+            self.client_seq = self.client_seq.wrapping_add(1);
+        }
+    }
+
+    /// the server shut down its write half
+    fn server_shutdown(&mut self) {
+        if !self.server_fin {
+            self.server_fin = true;
+            self.server_segment(FIN | ACK, &[]);
+            self.server_seq = self.server_seq.wrapping_add(1);
+            // the client acknowledges, as its kernel would
+            self.client_segment(ACK, &[]);
+        }
+    }
+}
+
+impl Drop for Flow {
+    fn drop(&mut self) {
+        // dropped without a shutdown, the kernel resets or closes
+        if !self.server_fin {
+            self.server_segment(RST | ACK, &[]);
+        }
+    }
+}
+
+/// a synthesized IP packet carrying a TCP segment
+fn packet(
+    from: SocketAddr,
+    to: SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+    ip_id: &AtomicU16,
+) -> Vec<u8> {
+    // TCP header without options
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&from.port().to_be_bytes());
+    segment.extend_from_slice(&to.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(5 << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&u16::MAX.to_be_bytes());
+    // checksum, then urgent pointer
+    segment.extend_from_slice(&[0; 4]);
+    segment.extend_from_slice(payload);
+
+    let mut packet = Vec::with_capacity(40 + segment.len());
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            // checksum over a pseudo header and the segment
+            let mut pseudo = Vec::with_capacity(12);
+            pseudo.extend_from_slice(&source.octets());
+            pseudo.extend_from_slice(&destination.octets());
+            pseudo.extend_from_slice(&[0, 6]);
+            pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            let sum = checksum(&[&pseudo, &segment]);
+            segment[16..18].copy_from_slice(&sum.to_be_bytes());
+
+            // IPv4 header without options, don't fragment
+            let mut header = Vec::with_capacity(20);
+            header.extend_from_slice(&[0x45, 0]);
+            header.extend_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+            header.extend_from_slice(&ip_id.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+            header.extend_from_slice(&0x4000u16.to_be_bytes());
+            header.extend_from_slice(&[64, 6, 0, 0]);
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&destination.octets());
+            let sum = checksum(&[&header]);
+            header[10..12].copy_from_slice(&sum.to_be_bytes());
+
+            packet.extend_from_slice(&header);
+        }
+        (source, destination) => {
+            // addresses were normalized, only IPv6 is left
+            let source = ipv6(source);
+            let destination = ipv6(destination);
+            let mut pseudo = Vec::with_capacity(40);
+            pseudo.extend_from_slice(&source.octets());
+            pseudo.extend_from_slice(&destination.octets());
+            pseudo.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 6]);
+            let sum = checksum(&[&pseudo, &segment]);
+            segment[16..18].copy_from_slice(&sum.to_be_bytes());
+
+            // IPv6 header, TCP next
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[6, 64]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+        }
+    }
+    packet.extend_from_slice(&segment);
+
+    packet
+}
+
+/// an address as IPv6
+fn ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Internet checksum (RFC 1071) over consecutive parts
+pub fn checksum(parts: &[&[u8]]) -> u16 {
+    // big endian 16-bit words, the last one padded with zero
+    let bytes = parts.concat();
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|word| {
+            u32::from(u16::from_be_bytes([
+                word[0],
+                word.get(1).copied().unwrap_or(0),
+            ]))
+        })
+        .sum();
+    // fold carries
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// stream writing what is read from and written to it into a capture
+pub struct Captured<S> {
+    inner: S,
+    flow: Flow,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Captured<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // remember how much was there before
+        let before = buf.filled().len();
+        let asked = buf.remaining() > 0;
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        // data, or the end of the client's stream
+        if let Poll::Ready(Ok(())) = result {
+            let data = &buf.filled()[before..];
+            if !data.is_empty() {
+                this.flow.received(data);
+            } else if asked {
+                this.flow.client_shutdown();
+            }
+        }
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Captured<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // capture only what was actually written
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.flow.sent(&buf[..n]);
+        }
+
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_shutdown(cx);
+        if let Poll::Ready(Ok(())) = result {
+            this.flow.server_shutdown();
+        }
+
+        result
+    }
+}
@@ -0,0 +1,120 @@
+// self-checking echo: every frame is answered with a checksum of what the server received
+//
+// frames are a 4-byte big-endian length followed by the payload; the server answers each one
+// with the same framing, the payload then the checksum (append) or the checksum alone (answer).
+// CRC32 checksums are 4 big-endian bytes, xxHash64 ones 8
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// use clap to read options from command line
+use clap::ValueEnum;
+
+// use xxhash-rust for xxHash64
+use xxhash_rust::xxh64::xxh64;
+
+// types for I/O results and socket address
+use std::io;
+use std::net::SocketAddr;
+
+/// largest accepted frame
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// checksum computed over each frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    /// CRC-32 (IEEE), 4 bytes
+    Crc32,
+    /// xxHash64 with seed 0, 8 bytes
+    Xxhash64,
+}
+
+impl Algorithm {
+    /// checksum of `data`, big-endian
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Crc32 => crc32fast::hash(data).to_be_bytes().to_vec(),
+            Algorithm::Xxhash64 => xxh64(data, 0).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// what the server answers each frame with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumMode {
+    /// the payload followed by its checksum
+    Append,
+    /// only the checksum
+    Answer,
+}
+
+/// read one frame, `None` when the stream ends between frames
+pub async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<Vec<u8>>> {
+    // length, or the end of the stream
+    let mut length = [0u8; 4];
+    if stream.read(&mut length[..1]).await? == 0 {
+        return Ok(None);
+    }
+    stream.read_exact(&mut length[1..]).await?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", length),
+        ));
+    }
+
+    // payload
+    let mut frame = vec![0u8; length];
+    stream.read_exact(&mut frame).await?;
+
+    Ok(Some(frame))
+}
+
+/// write one frame made of `parts`, in a single write
+pub async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, parts: &[&[u8]]) -> io::Result<()> {
+    let length: usize = parts.iter().map(|part| part.len()).sum();
+    let mut frame = Vec::with_capacity(4 + length);
+    frame.extend_from_slice(&(length as u32).to_be_bytes());
+    for part in parts {
+        frame.extend_from_slice(part);
+    }
+    stream.write_all(&frame).await
+}
+
+/// answer every frame with its checksum until the client shuts down its write half,
+/// returning the count of payload bytes received
+pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    algorithm: Algorithm,
+    mode: ChecksumMode,
+    quiet: bool,
+) -> io::Result<u64> {
+    // total count of received bytes
+    let mut received: u64 = 0;
+
+    while let Some(frame) = read_frame(stream).await? {
+        received += frame.len() as u64;
+        let checksum = algorithm.digest(&frame);
+
+        // print to screen
+        if !quiet {
+            println!(
+                "From {:?}: frame of {} bytes, checksum {}",
+                client_address,
+                frame.len(),
+                hex::encode(&checksum)
+            );
+        }
+
+        // echo with the checksum, or the checksum alone
+        match mode {
+            ChecksumMode::Append => write_frame(stream, &[&frame, &checksum]).await?,
+            ChecksumMode::Answer => write_frame(stream, &[&checksum]).await?,
+        }
+        stream.flush().await?;
+    }
+
+    Ok(received)
+}
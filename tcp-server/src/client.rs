@@ -0,0 +1,194 @@
+// client of the checksum protocol, verifying every frame end to end
+//
+// random frames are sent while answers are read; only the checksums of frames in flight are
+// kept, so soak tests can run for as long as needed. In append mode the echoed payload and the
+// checksum of the server tell which direction corrupted a frame
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+// use anyhow for error handling
+use anyhow::{bail, Context, Result};
+
+// use clap to read options from command line
+use clap::Args;
+
+// use rand for payloads
+use rand::{Rng, RngCore};
+
+// types for socket address
+use std::net::SocketAddr;
+
+// framing and checksums
+use crate::checksum::{read_frame, write_frame, Algorithm, ChecksumMode, MAX_FRAME_SIZE};
+
+/// frames sent ahead of their answers
+const IN_FLIGHT: usize = 64;
+
+/// options of the checking client
+#[derive(Debug, Clone, Args)]
+pub struct ClientOptions {
+    /// server speaking --protocol checksum
+    pub address: SocketAddr,
+
+    /// frames to send, 0 to go on until the connection fails
+    #[arg(long, default_value_t = 1000)]
+    pub frames: u64,
+
+    /// largest frame sent, sizes are random from 1 byte up to this
+    #[arg(long, value_name = "BYTES", default_value_t = 4096)]
+    pub frame_size: usize,
+}
+
+/// what the client found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Report {
+    /// frames answered
+    pub frames: u64,
+    /// payload bytes sent
+    pub bytes: u64,
+    /// frames whose answer did not match
+    pub corrupted: u64,
+    /// of those, frames the server received differently from what was sent
+    pub upstream: u64,
+    /// of those, frames corrupted on the way back
+    pub downstream: u64,
+}
+
+impl Report {
+    /// account for an answer checked against the checksum of the frame sent,
+    /// returning where it was corrupted, if it was
+    fn check(
+        &mut self,
+        algorithm: Algorithm,
+        mode: ChecksumMode,
+        expected: &[u8],
+        answer: &[u8],
+    ) -> Option<&'static str> {
+        self.frames += 1;
+        let (upstream, downstream) = match mode {
+            // the direction cannot be told from a checksum alone
+            ChecksumMode::Answer if answer == expected => return None,
+            ChecksumMode::Answer => (false, false),
+            // payload then checksum
+            ChecksumMode::Append if answer.len() < expected.len() => (false, true),
+            ChecksumMode::Append => {
+                let (payload, checksum) = answer.split_at(answer.len() - expected.len());
+                let echoed = algorithm.digest(payload);
+                match (checksum == expected, echoed == checksum) {
+                    (true, true) => return None,
+                    // the payload made it both ways, the checksum did not make it back
+                    _ if echoed == expected => (false, true),
+                    // the server got the frame, the payload did not make it back
+                    (true, false) => (false, true),
+                    // the server got something else and echoed it faithfully
+                    (false, true) => (true, false),
+                    (false, false) => (true, true),
+                }
+            }
+        };
+        self.corrupted += 1;
+        self.upstream += upstream as u64;
+        self.downstream += downstream as u64;
+
+        Some(match (upstream, downstream) {
+            (true, true) => "both ways",
+            (true, false) => "upstream",
+            (false, true) => "downstream",
+            (false, false) => "in an unknown direction",
+        })
+    }
+}
+
+/// send random frames over `stream`, checking every answer
+pub async fn check<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    algorithm: Algorithm,
+    mode: ChecksumMode,
+    options: &ClientOptions,
+    quiet: bool,
+) -> Result<Report> {
+    // answers would not fit a frame
+    if options.frame_size == 0 || options.frame_size > MAX_FRAME_SIZE - 8 {
+        bail!("--frame-size must be from 1 to {}", MAX_FRAME_SIZE - 8);
+    }
+
+    // split stream
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    // checksums of frames waiting for their answer
+    let (sent, mut to_check) = mpsc::channel::<Vec<u8>>(IN_FLIGHT);
+
+    // send frames, then shut down the write half
+    let send = async move {
+        // payload bytes sent
+        let mut bytes = 0;
+        let mut count = 0;
+        while options.frames == 0 || count < options.frames {
+            // random size and content
+            let payload = {
+                let mut rng = rand::rng();
+                let mut payload = vec![0u8; rng.random_range(1..=options.frame_size)];
+                rng.fill_bytes(&mut payload);
+                payload
+            };
+            write_frame(&mut writer, &[&payload])
+                .await
+                .context("Failed to send frame")?;
+            bytes += payload.len() as u64;
+            count += 1;
+
+            // the reader stops on error
+            if sent.send(algorithm.digest(&payload)).await.is_err() {
+                break;
+            }
+        }
+        writer.shutdown().await.context("Failed to shut down")?;
+
+        Ok(bytes)
+    };
+
+    // check answers in the order frames were sent
+    let receive = async move {
+        let mut report = Report::default();
+        while let Some(expected) = to_check.recv().await {
+            let answer = match read_frame(&mut reader)
+                .await
+                .context("Failed to read answer")?
+            {
+                Some(answer) => answer,
+                None => bail!(
+                    "Server closed the connection after {} frames",
+                    report.frames
+                ),
+            };
+
+            // print to screen
+            let corrupted = report.check(algorithm, mode, &expected, &answer);
+            if let Some(direction) = corrupted.filter(|_| !quiet) {
+                println!("Frame {} corrupted {}", report.frames, direction);
+            }
+        }
+
+        Ok(report)
+    };
+
+    // run both directions until both are done
+    let (bytes, report) = tokio::try_join!(send, receive)?;
+
+    Ok(Report { bytes, ..report })
+}
+
+/// connect to the server and check frames as configured
+pub async fn run(
+    algorithm: Algorithm,
+    mode: ChecksumMode,
+    options: &ClientOptions,
+    quiet: bool,
+) -> Result<Report> {
+    let stream = TcpStream::connect(options.address)
+        .await
+        .with_context(|| format!("Failed to connect to {}", options.address))?;
+    check(stream, algorithm, mode, options, quiet).await
+}
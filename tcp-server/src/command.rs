@@ -0,0 +1,201 @@
+// line protocol: every line is echoed, except control commands
+//
+//   STATS            counters of this session
+//   ECHO <text>      echo text even if it looks like a command
+//   DELAY <ms>       wait before every echo
+//   MODE <mode>      plain, upper, reverse or hex
+//   QUIT             close the session
+
+// use tokio for async runtime
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+// types for I/O results, formatting, socket address and timing
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// longest accepted line
+const MAX_LINE: u64 = 64 * 1024;
+
+/// how echoed lines are transformed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Plain,
+    Upper,
+    Reverse,
+    Hex,
+}
+
+impl Mode {
+    /// apply to one line, without its line ending
+    pub fn apply(self, line: &[u8]) -> Vec<u8> {
+        match self {
+            Mode::Plain => line.to_vec(),
+            Mode::Upper => String::from_utf8_lossy(line).to_uppercase().into_bytes(),
+            Mode::Reverse => String::from_utf8_lossy(line)
+                .chars()
+                .rev()
+                .collect::<String>()
+                .into_bytes(),
+            Mode::Hex => hex::encode(line).into_bytes(),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Mode::Plain => "plain",
+            Mode::Upper => "upper",
+            Mode::Reverse => "reverse",
+            Mode::Hex => "hex",
+        })
+    }
+}
+
+/// one line from the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command<'a> {
+    Stats,
+    Echo(&'a [u8]),
+    Delay(Duration),
+    Mode(Mode),
+    Quit,
+    /// anything else, echoed as is
+    Text(&'a [u8]),
+}
+
+/// parse a line without its line ending
+pub fn parse(line: &[u8]) -> Result<Command<'_>, String> {
+    // keyword and argument
+    let (keyword, argument) = match line.iter().position(|&byte| byte == b' ') {
+        Some(space) => (&line[..space], Some(&line[space + 1..])),
+        None => (line, None),
+    };
+    // keywords are case-insensitive
+    let keyword = keyword.to_ascii_uppercase();
+    // argument as text
+    let text = argument.map(|argument| String::from_utf8_lossy(argument).trim().to_string());
+
+    match (keyword.as_slice(), text) {
+        (b"STATS", None) => Ok(Command::Stats),
+        (b"QUIT", None) => Ok(Command::Quit),
+        (b"ECHO", _) => Ok(Command::Echo(argument.unwrap_or_default())),
+        (b"DELAY", Some(ms)) => ms
+            .parse()
+            .map(|ms| Command::Delay(Duration::from_millis(ms)))
+            .map_err(|_| format!("invalid delay: {}", ms)),
+        (b"MODE", Some(mode)) => match mode.to_ascii_lowercase().as_str() {
+            "plain" => Ok(Command::Mode(Mode::Plain)),
+            "upper" => Ok(Command::Mode(Mode::Upper)),
+            "reverse" => Ok(Command::Mode(Mode::Reverse)),
+            "hex" => Ok(Command::Mode(Mode::Hex)),
+            _ => Err(format!("unknown mode: {}", mode)),
+        },
+        (b"DELAY", None) | (b"MODE", None) => Err("missing argument".to_string()),
+        _ => Ok(Command::Text(line)),
+    }
+}
+
+/// settings and counters of one session
+struct Session {
+    mode: Mode,
+    delay: Duration,
+    started: Instant,
+    lines: u64,
+    received: u64,
+}
+
+/// serve the line protocol until the client quits or shuts down its write half,
+/// returning the count of received bytes
+pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    quiet: bool,
+) -> io::Result<u64> {
+    // read lines, write through
+    let mut stream = BufReader::new(stream);
+    // fresh session
+    let mut session = Session {
+        mode: Mode::Plain,
+        delay: Duration::ZERO,
+        started: Instant::now(),
+        lines: 0,
+        received: 0,
+    };
+    // current line
+    let mut line = Vec::new();
+
+    loop {
+        // read next line, with its line ending
+        line.clear();
+        let n = (&mut stream)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await?;
+        // client finished sending
+        if n == 0 {
+            return Ok(session.received);
+        }
+        // add to sum
+        session.received += n as u64;
+        session.lines += 1;
+
+        // answer with the same line ending
+        let content_length = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .map_or(line.len(), <[u8]>::len);
+        let (content, ending) = line.split_at(content_length);
+
+        // print to screen
+        if !quiet {
+            println!(
+                "From {:?}: {}",
+                client_address,
+                String::from_utf8_lossy(content)
+            );
+        }
+
+        // act on the line
+        let reply = match parse(content) {
+            Ok(Command::Stats) => format!(
+                "STATS lines={} bytes={} uptime_ms={} mode={} delay_ms={}",
+                session.lines,
+                session.received,
+                session.started.elapsed().as_millis(),
+                session.mode,
+                session.delay.as_millis()
+            )
+            .into_bytes(),
+            Ok(Command::Delay(delay)) => {
+                session.delay = delay;
+                format!("OK delay {}ms", delay.as_millis()).into_bytes()
+            }
+            Ok(Command::Mode(mode)) => {
+                session.mode = mode;
+                format!("OK mode {}", mode).into_bytes()
+            }
+            Ok(Command::Quit) => {
+                stream.write_all(b"BYE").await?;
+                stream.write_all(ending).await?;
+                stream.flush().await?;
+                return Ok(session.received);
+            }
+            Ok(Command::Echo(text)) | Ok(Command::Text(text)) => {
+                // wait as asked to
+                if !session.delay.is_zero() {
+                    tokio::time::sleep(session.delay).await;
+                }
+                session.mode.apply(text)
+            }
+            Err(e) => format!("ERR {}", e).into_bytes(),
+        };
+
+        // send reply
+        stream.write_all(&reply).await?;
+        stream.write_all(ending).await?;
+        stream.flush().await?;
+    }
+}
@@ -0,0 +1,131 @@
+// transparent stream compression, negotiated with a one-byte header sent by the client
+//
+//   0  no compression
+//   1  deflate (RFC 1951)
+//   2  zstd
+//
+// both directions use the chosen algorithm after the header
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+// types for I/O results, counters and polling
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// compression algorithm chosen by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Algorithm {
+    /// algorithm announced by a header byte
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Algorithm::None),
+            1 => Some(Algorithm::Deflate),
+            2 => Some(Algorithm::Zstd),
+            _ => None,
+        }
+    }
+
+    /// header byte announcing this algorithm
+    pub fn byte(self) -> u8 {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Deflate => 1,
+            Algorithm::Zstd => 2,
+        }
+    }
+}
+
+/// read the header byte sent by the client
+pub async fn negotiate<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Algorithm> {
+    // one byte
+    let byte = stream.read_u8().await?;
+
+    Algorithm::from_byte(byte).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown compression algorithm {}", byte),
+        )
+    })
+}
+
+/// compressed and uncompressed sizes of what the client sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ratio {
+    /// bytes on the wire, after the header
+    pub compressed: u64,
+    /// bytes after decompression
+    pub uncompressed: u64,
+}
+
+impl Ratio {
+    /// uncompressed size over compressed size
+    pub fn ratio(&self) -> f64 {
+        if self.compressed == 0 {
+            1.0
+        } else {
+            self.uncompressed as f64 / self.compressed as f64
+        }
+    }
+}
+
+/// reader counting the bytes read through it
+pub struct Counted<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> Counted<R> {
+    /// count into `count`
+    pub fn new(inner: R, count: Arc<AtomicU64>) -> Self {
+        Counted { inner, count }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // remember how much was there before
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        // count what was added
+        let read = buf.filled().len() - before;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+
+        result
+    }
+}
+
+/// writer whose shutdown only flushes, so finishing a compressed stream
+/// leaves the connection open for the usual half-close handling
+pub struct KeepOpen<W>(pub W);
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for KeepOpen<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
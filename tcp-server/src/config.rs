@@ -0,0 +1,255 @@
+// use clap to read options from command line
+use clap::{Parser, Subcommand};
+
+// types for addresses and file paths
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+// capture file format
+use crate::capture::Format;
+// self-checking echo
+use crate::checksum::{Algorithm, ChecksumMode};
+// checking client
+use crate::client::ClientOptions;
+// ways of copying bytes back
+use crate::echo::CopyMode;
+// format of HTTP echo
+use crate::http::HttpFormat;
+// dropping privileges after binding
+use crate::privileges::PrivilegeOptions;
+// protocol spoken with clients
+use crate::server::Protocol;
+// layout of runtimes and threads
+use crate::runtime::RuntimeOptions;
+// SOCKS5 targets
+use crate::socks::Target;
+// options of sockets
+use crate::socket::SocketOptions;
+
+/// TCP echo server
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Config {
+    /// address to listen on
+    #[arg(long, default_value = "0.0.0.0:8080")]
+    pub listen: SocketAddr,
+
+    /// protocol spoken with clients
+    #[arg(long, value_enum, default_value_t = Protocol::Tcp)]
+    pub protocol: Protocol,
+
+    /// how HTTP requests are described in responses
+    #[arg(long, value_enum, default_value_t = HttpFormat::Text)]
+    pub http_format: HttpFormat,
+
+    /// bytes either side may send on a virtual stream before the other grants more
+    #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024)]
+    pub mux_window: u32,
+
+    /// virtual streams a connection may have open at the same time
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 256,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub mux_max_streams: u32,
+
+    /// CONNECT target answered by the echo itself in SOCKS5 mode
+    #[arg(long, value_name = "HOST:PORT", default_value = "echo.invalid:7")]
+    pub socks_echo_target: Target,
+
+    /// let SOCKS5 clients connect to targets other than the echo
+    #[arg(long)]
+    pub socks_upstream: bool,
+
+    /// time given to SOCKS5 upstream connections to be established
+    #[arg(long, value_name = "MS", default_value_t = 10000)]
+    pub socks_connect_timeout: u64,
+
+    /// how received bytes are copied back
+    #[arg(long, value_enum, default_value_t = CopyMode::Loop)]
+    pub copy_mode: CopyMode,
+
+    /// buffer size of the buffered and splice copy modes
+    #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024)]
+    pub buffer_size: usize,
+
+    /// do not print received data
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// expect a header byte choosing the compression of the session:
+    /// 0 none, 1 deflate, 2 zstd
+    #[arg(long)]
+    pub compression: bool,
+
+    /// after the client shuts down its write half, keep echoing what is still
+    /// pending, then keep our write half open this long, for protocols that expect more output
+    #[arg(long, value_name = "MS")]
+    pub half_close_linger: Option<u64>,
+
+    /// require clients to prove knowledge of this pre-shared key
+    #[arg(long, value_name = "KEY")]
+    pub auth_key: Option<String>,
+
+    /// require clients to prove knowledge of one of the keys in this file, one per line
+    #[arg(long, value_name = "FILE")]
+    pub auth_tokens: Option<PathBuf>,
+
+    /// time given to clients to answer the authentication challenge
+    #[arg(long, value_name = "MS", default_value_t = 5000)]
+    pub auth_timeout: u64,
+
+    /// forget the failed authentication attempts of an address after this long without another
+    #[arg(long, value_name = "MS", default_value_t = 3_600_000)]
+    pub auth_failure_window: u64,
+
+    /// keep statistics per client in this file, loaded at startup
+    #[arg(long, value_name = "FILE", global = true)]
+    pub stats_file: Option<PathBuf>,
+
+    /// seconds between two snapshots of statistics
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub stats_interval: u64,
+
+    /// anchor the BLAKE2 hash of every session transcript as a proof-of-existence
+    /// claim on the node serving JSON-RPC at this URL, e.g. http://127.0.0.1:9933
+    #[arg(long, value_name = "URL")]
+    pub poe_node: Option<String>,
+
+    /// hex sr25519 mini secret key signing claims, `//Alice` by default
+    #[arg(long, value_name = "HEX", default_value = crate::poe::ALICE_SEED)]
+    pub poe_seed: String,
+
+    /// index of the PoE pallet in the runtime
+    #[arg(long, value_name = "INDEX", default_value_t = 8)]
+    pub poe_pallet_index: u8,
+
+    /// index of `create_claim` in the PoE pallet
+    #[arg(long, value_name = "INDEX", default_value_t = 0)]
+    pub poe_call_index: u8,
+
+    /// time given to the PoE node to answer each JSON-RPC call
+    #[arg(long, value_name = "MS", default_value_t = 10000)]
+    pub poe_timeout: u64,
+
+    /// also accept QUIC connections on this UDP address, echoing every bidirectional stream
+    #[arg(long, value_name = "ADDRESS")]
+    pub quic: Option<SocketAddr>,
+
+    /// PEM certificate chain of the QUIC listener, generated self-signed if missing with its key
+    #[arg(long, value_name = "FILE", requires_all = ["quic", "quic_key"])]
+    pub quic_cert: Option<PathBuf>,
+
+    /// PEM private key of the QUIC listener
+    #[arg(long, value_name = "FILE", requires_all = ["quic", "quic_cert"])]
+    pub quic_key: Option<PathBuf>,
+
+    /// PEM certificates of the authorities QUIC clients may present a certificate from,
+    /// audited by fingerprint; clients without one are served all the same
+    #[arg(long, value_name = "FILE", requires = "quic")]
+    pub quic_client_ca: Option<PathBuf>,
+
+    /// write the traffic of every connection to this file, with synthesized TCP/IP headers
+    #[arg(long, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+
+    /// format of the capture file
+    #[arg(long, value_enum, default_value_t = Format::Pcap, requires = "capture")]
+    pub capture_format: Format,
+
+    /// rotate the capture file before it grows beyond this size
+    #[arg(long, value_name = "BYTES", requires = "capture")]
+    pub capture_max_size: Option<u64>,
+
+    /// rotated capture files kept, as FILE.1 (newest) to FILE.<COUNT>
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 5,
+        requires = "capture_max_size"
+    )]
+    pub capture_files: usize,
+
+    /// append a JSON line to this file for every connection once it ends
+    #[arg(long, value_name = "FILE")]
+    pub audit_log: Option<PathBuf>,
+
+    /// rotate the audit log before it grows beyond this size
+    #[arg(long, value_name = "BYTES", requires = "audit_log")]
+    pub audit_max_size: Option<u64>,
+
+    /// rotated audit logs kept, as FILE.1 (newest) to FILE.<COUNT>
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 5,
+        requires = "audit_max_size"
+    )]
+    pub audit_files: usize,
+
+    /// accept administration commands on this TCP address, such as killing a connection;
+    /// anyone reaching it may, so keep it on loopback
+    #[arg(long, value_name = "ADDRESS", requires = "audit_log")]
+    pub admin: Option<SocketAddr>,
+
+    /// checksum of --protocol checksum frames
+    #[arg(long, value_enum, default_value_t = Algorithm::Crc32)]
+    pub checksum: Algorithm,
+
+    /// what --protocol checksum answers each frame with
+    #[arg(long, value_enum, default_value_t = ChecksumMode::Append)]
+    pub checksum_mode: ChecksumMode,
+
+    /// answer each received chunk with what this WebAssembly module makes of it
+    #[arg(long, value_name = "FILE")]
+    pub wasm_plugin: Option<PathBuf>,
+
+    /// bytes of memory the plugin instance of a connection may use
+    #[arg(long, value_name = "BYTES", default_value_t = 16 * 1024 * 1024, requires = "wasm_plugin")]
+    pub wasm_memory_limit: usize,
+
+    /// fuel the plugin instance of a connection may burn, roughly one unit per instruction
+    #[arg(
+        long,
+        value_name = "UNITS",
+        default_value_t = 1_000_000_000,
+        requires = "wasm_plugin"
+    )]
+    pub wasm_fuel: u64,
+
+    /// socket tuning
+    #[command(flatten)]
+    pub socket: SocketOptions,
+
+    /// runtime tuning
+    #[command(flatten)]
+    pub runtime: RuntimeOptions,
+
+    /// dropping privileges
+    #[command(flatten)]
+    pub privileges: PrivilegeOptions,
+
+    /// run a tool instead of the server
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+/// tools run instead of the server
+#[derive(Debug, Clone, Subcommand)]
+pub enum Action {
+    /// print the statistics saved in --stats-file
+    Stats {
+        /// only show this client
+        client: Option<IpAddr>,
+    },
+    /// send random frames to a --protocol checksum server and verify every answer,
+    /// with the same --checksum and --checksum-mode
+    Client(ClientOptions),
+}
@@ -0,0 +1,108 @@
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+// use clap to read options from command line
+use clap::ValueEnum;
+
+// types for I/O results and socket address
+use std::io;
+use std::net::SocketAddr;
+
+const BUFFER_SIZE: usize = 1024;
+
+/// number of received chunks waiting to be echoed before reading pauses
+const PENDING_CHUNKS: usize = 16;
+
+/// how received bytes are copied back to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CopyMode {
+    /// read into a small buffer, print and echo each chunk
+    Loop,
+    /// copy through one large buffer reused for the whole connection
+    Buffered,
+    /// move bytes inside the kernel with splice(2) through a pipe (Linux only)
+    Splice,
+}
+
+/// echo chunk by chunk, printing each one unless `quiet`
+///
+/// reading goes on while earlier chunks are being written back
+pub async fn echo_loop<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    quiet: bool,
+) -> io::Result<u64> {
+    // split stream
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    // received chunks not echoed yet
+    let (pending, mut to_echo) = mpsc::channel::<Vec<u8>>(PENDING_CHUNKS);
+
+    // read from client until it shuts down its write half
+    let receive = async move {
+        // buffer for incoming message
+        let mut buffer = [0u8; BUFFER_SIZE];
+        // total count of received bytes
+        let mut echoed_bytes_count: u64 = 0;
+
+        // loop to handle data
+        loop {
+            // client finished sending
+            let n = match reader.read(&mut buffer).await? {
+                0 => return io::Result::Ok(echoed_bytes_count),
+                n => n,
+            };
+            // add to sum
+            echoed_bytes_count += n as u64;
+
+            // print to screen
+            if !quiet {
+                println!(
+                    "From {:?}: {}",
+                    client_address,
+                    String::from_utf8_lossy(&buffer[..n])
+                );
+            }
+
+            // queue for echo, the writer only stops on error
+            if pending.send(buffer[..n].to_vec()).await.is_err() {
+                return Ok(echoed_bytes_count);
+            }
+        }
+    };
+
+    // write back everything received
+    let echo = async move {
+        // echo until the reader is done and the queue is drained
+        while let Some(chunk) = to_echo.recv().await {
+            writer.write_all(&chunk).await?;
+            // push it out, in case the stream buffers
+            writer.flush().await?;
+        }
+
+        Ok(())
+    };
+
+    // run both directions until both are done
+    let (echoed_bytes_count, ()) = tokio::try_join!(receive, echo)?;
+
+    Ok(echoed_bytes_count)
+}
+
+/// echo through a buffer of `buffer_size` bytes, allocated once per connection
+pub async fn echo_buffered<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    buffer_size: usize,
+) -> io::Result<u64> {
+    // split stream
+    let (reader, mut writer) = tokio::io::split(stream);
+    // the buffer is reused for every read
+    let mut reader = BufReader::with_capacity(buffer_size, reader);
+
+    // copy until the client shuts down its write half
+    let echoed_bytes_count = tokio::io::copy_buf(&mut reader, &mut writer).await?;
+    // make sure nothing is left behind
+    writer.flush().await?;
+
+    Ok(echoed_bytes_count)
+}
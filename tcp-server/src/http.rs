@@ -0,0 +1,455 @@
+// HTTP/1.1 echo: every request is answered with a description of itself
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// use clap to read options from command line
+use clap::ValueEnum;
+
+// types for I/O results and socket address
+use std::io;
+use std::net::SocketAddr;
+
+/// most headers accepted in one request
+const MAX_HEADERS: usize = 64;
+
+/// largest accepted request head (request line and headers)
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// largest accepted request body
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// size of chunks in chunked responses
+const RESPONSE_CHUNK_SIZE: usize = 8 * 1024;
+
+/// how the received request is described in the response body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HttpFormat {
+    /// the request as received, with the body decoded
+    Text,
+    /// a JSON object with method, path, version, headers and body
+    Json,
+}
+
+/// a parsed request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// minor version, `1` for HTTP/1.1
+    pub version: u8,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// first value of a header, matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// whether a comma separated header contains `token`
+    fn header_has(&self, name: &str, token: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// whether the body was sent with chunked transfer encoding
+    pub fn is_chunked(&self) -> bool {
+        self.header_has("transfer-encoding", "chunked")
+    }
+
+    /// whether the client wants the connection kept open after the response
+    pub fn keep_alive(&self) -> bool {
+        if self.version == 0 {
+            // HTTP/1.0 closes unless asked otherwise
+            self.header_has("connection", "keep-alive")
+        } else {
+            // HTTP/1.1 keeps open unless asked otherwise
+            !self.header_has("connection", "close")
+        }
+    }
+
+    /// describe the request in the given format
+    pub fn describe(&self, format: HttpFormat) -> Vec<u8> {
+        match format {
+            HttpFormat::Text => {
+                // request line
+                let mut text = format!("{} {} HTTP/1.{}\r\n", self.method, self.path, self.version);
+                // headers
+                for (name, value) in &self.headers {
+                    text.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                text.push_str("\r\n");
+
+                // body as received
+                let mut text = text.into_bytes();
+                text.extend_from_slice(&self.body);
+                text
+            }
+            HttpFormat::Json => serde_json::json!({
+                "method": self.method,
+                "path": self.path,
+                "version": format!("HTTP/1.{}", self.version),
+                "headers": self.headers,
+                "body": String::from_utf8_lossy(&self.body),
+            })
+            .to_string()
+            .into_bytes(),
+        }
+    }
+}
+
+/// result of parsing the head of a request
+#[derive(Debug, PartialEq, Eq)]
+pub enum Head {
+    /// more bytes are needed
+    Partial,
+    /// a request without its body, and the length of its head
+    Complete(Request, usize),
+}
+
+/// parse a request line and headers from the start of `buffer`
+pub fn parse_head(buffer: &[u8]) -> Result<Head, httparse::Error> {
+    // room for headers
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+
+    // parse as much as is available
+    let length = match request.parse(buffer)? {
+        httparse::Status::Partial => return Ok(Head::Partial),
+        httparse::Status::Complete(length) => length,
+    };
+
+    // copy out of the buffer
+    let request = Request {
+        method: request.method.unwrap_or_default().to_string(),
+        path: request.path.unwrap_or_default().to_string(),
+        version: request.version.unwrap_or(1),
+        headers: request
+            .headers
+            .iter()
+            .map(|header| {
+                (
+                    header.name.to_string(),
+                    String::from_utf8_lossy(header.value).into_owned(),
+                )
+            })
+            .collect(),
+        body: Vec::new(),
+    };
+
+    Ok(Head::Complete(request, length))
+}
+
+/// parse a chunk size line such as `1a;name=value`
+pub fn parse_chunk_size(line: &[u8]) -> Option<usize> {
+    // drop chunk extensions
+    let size = line.split(|&byte| byte == b';').next()?;
+    // hexadecimal size
+    let size = std::str::from_utf8(size).ok()?.trim();
+    usize::from_str_radix(size, 16).ok()
+}
+
+/// a client connection with bytes read ahead of the current request
+struct Connection<'a, S> {
+    stream: &'a mut S,
+    buffer: Vec<u8>,
+    /// total count of bytes read from the client
+    received: u64,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> Connection<'a, S> {
+    /// read more bytes into the buffer, false on end of stream
+    async fn fill(&mut self) -> io::Result<bool> {
+        // read into a scratch buffer
+        let mut chunk = [0u8; 8 * 1024];
+        let n = self.stream.read(&mut chunk).await?;
+        // keep what was read
+        self.buffer.extend_from_slice(&chunk[..n]);
+        self.received += n as u64;
+
+        Ok(n > 0)
+    }
+
+    /// take the next `length` bytes
+    async fn take(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        // wait for enough bytes
+        while self.buffer.len() < length {
+            if !self.fill().await? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+
+        Ok(self.buffer.drain(..length).collect())
+    }
+
+    /// take the next line without its CRLF
+    async fn line(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            // a complete line is available
+            if let Some(end) = self.buffer.windows(2).position(|pair| pair == b"\r\n") {
+                let mut line: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                line.truncate(end);
+                return Ok(line);
+            }
+            // refuse endless lines
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(invalid("line too long"));
+            }
+            // wait for more
+            if !self.fill().await? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// read the next request head, `None` when the client closed between requests
+    async fn head(&mut self) -> io::Result<Option<Request>> {
+        loop {
+            match parse_head(&self.buffer) {
+                // drop the head from the buffer
+                Ok(Head::Complete(request, length)) => {
+                    self.buffer.drain(..length);
+                    return Ok(Some(request));
+                }
+                // refuse endless heads
+                Ok(Head::Partial) if self.buffer.len() > MAX_HEAD_SIZE => {
+                    return Err(invalid("request head too large"))
+                }
+                // wait for more
+                Ok(Head::Partial) => {
+                    if !self.fill().await? {
+                        // closing between requests is fine
+                        return if self.buffer.is_empty() {
+                            Ok(None)
+                        } else {
+                            Err(io::ErrorKind::UnexpectedEof.into())
+                        };
+                    }
+                }
+                Err(e) => return Err(invalid(e)),
+            }
+        }
+    }
+
+    /// read a chunked body, appending trailers to the headers
+    async fn chunked_body(&mut self, request: &mut Request) -> io::Result<()> {
+        loop {
+            // size of next chunk
+            let line = self.line().await?;
+            let size = parse_chunk_size(&line).ok_or_else(|| invalid("invalid chunk size"))?;
+
+            // last chunk, followed by trailers
+            if size == 0 {
+                // trailers are bounded like headers
+                let mut trailer_size = 0;
+                loop {
+                    let line = self.line().await?;
+                    if line.is_empty() {
+                        return Ok(());
+                    }
+                    trailer_size += line.len() + 2;
+                    if trailer_size > MAX_HEAD_SIZE {
+                        return Err(invalid("trailers too large"));
+                    }
+                    if request.headers.len() >= MAX_HEADERS {
+                        return Err(invalid("too many headers and trailers"));
+                    }
+                    // keep trailers next to the headers
+                    let line = String::from_utf8_lossy(&line);
+                    let (name, value) = line
+                        .split_once(':')
+                        .ok_or_else(|| invalid("invalid trailer"))?;
+                    request
+                        .headers
+                        .push((name.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            // refuse huge bodies, without overflowing on huge chunk sizes
+            if size > MAX_BODY_SIZE - request.body.len() {
+                return Err(too_large());
+            }
+
+            // chunk data followed by CRLF
+            let data = self.take(size).await?;
+            request.body.extend_from_slice(&data);
+            if !self.line().await?.is_empty() {
+                return Err(invalid("missing CRLF after chunk"));
+            }
+        }
+    }
+
+    /// read the body announced by the request headers
+    async fn body(&mut self, request: &mut Request) -> io::Result<()> {
+        // let the client know it may send the body
+        if request.header_has("expect", "100-continue") {
+            self.stream
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .await?;
+        }
+
+        if request.is_chunked() {
+            self.chunked_body(request).await
+        } else if let Some(length) = request.header("content-length") {
+            // fixed length body
+            let length: usize = length
+                .trim()
+                .parse()
+                .map_err(|_| invalid("invalid content length"))?;
+            if length > MAX_BODY_SIZE {
+                return Err(too_large());
+            }
+            request.body = self.take(length).await?;
+            Ok(())
+        } else {
+            // no body
+            Ok(())
+        }
+    }
+}
+
+/// an error caused by a malformed request
+fn invalid(reason: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// an error caused by a request body beyond MAX_BODY_SIZE
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::QuotaExceeded, "request body too large")
+}
+
+/// write a response with `body`, chunked when asked to
+async fn respond<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    chunked: bool,
+    keep_alive: bool,
+    head_only: bool,
+) -> io::Result<()> {
+    // status line and headers
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nConnection: {}\r\n",
+        status,
+        content_type,
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    if chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+    } else {
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    }
+    stream.write_all(head.as_bytes()).await?;
+
+    // HEAD responses have no body
+    if !head_only {
+        if chunked {
+            // body in chunks, then the last chunk
+            for chunk in body.chunks(RESPONSE_CHUNK_SIZE) {
+                stream
+                    .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+                    .await?;
+                stream.write_all(chunk).await?;
+                stream.write_all(b"\r\n").await?;
+            }
+            stream.write_all(b"0\r\n\r\n").await?;
+        } else {
+            stream.write_all(body).await?;
+        }
+    }
+
+    stream.flush().await
+}
+
+/// answer requests until the client closes or asks to close, returning the count of received bytes
+pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    format: HttpFormat,
+    quiet: bool,
+) -> io::Result<u64> {
+    // buffered connection
+    let mut connection = Connection {
+        stream,
+        buffer: Vec::new(),
+        received: 0,
+    };
+    // content type of described requests
+    let content_type = match format {
+        HttpFormat::Text => "text/plain; charset=utf-8",
+        HttpFormat::Json => "application/json",
+    };
+
+    loop {
+        // read a whole request
+        let request = match connection.head().await {
+            Ok(Some(mut request)) => connection.body(&mut request).await.map(|()| request),
+            Ok(None) => return Ok(connection.received),
+            Err(e) => Err(e),
+        };
+        let request = match request {
+            Ok(request) => request,
+            // tell the client what was wrong before closing
+            Err(e)
+                if e.kind() == io::ErrorKind::InvalidData
+                    || e.kind() == io::ErrorKind::QuotaExceeded =>
+            {
+                let status = if e.kind() == io::ErrorKind::QuotaExceeded {
+                    "413 Payload Too Large"
+                } else {
+                    "400 Bad Request"
+                };
+                let body = format!("{}\n", e);
+                respond(
+                    connection.stream,
+                    status,
+                    "text/plain",
+                    body.as_bytes(),
+                    false,
+                    false,
+                    false,
+                )
+                .await?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+
+        // print to screen
+        if !quiet {
+            println!(
+                "From {:?}: {} {} ({} bytes of body)",
+                client_address,
+                request.method,
+                request.path,
+                request.body.len()
+            );
+        }
+
+        // reflect the request back
+        let keep_alive = request.keep_alive();
+        respond(
+            connection.stream,
+            "200 OK",
+            content_type,
+            &request.describe(format),
+            request.is_chunked(),
+            keep_alive,
+            request.method == "HEAD",
+        )
+        .await?;
+
+        // the client asked to close
+        if !keep_alive {
+            return Ok(connection.received);
+        }
+    }
+}
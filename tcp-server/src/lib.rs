@@ -0,0 +1,57 @@
+// Homework requires all statements to be commented
+
+// administration interface
+pub mod admin;
+// connection audit log
+pub mod audit;
+// authentication handshake
+pub mod auth;
+// traffic capture
+pub mod capture;
+// self-checking echo
+pub mod checksum;
+// checking client
+pub mod client;
+// line protocol with control commands
+pub mod command;
+// stream compression
+pub mod compression;
+// command line options
+pub mod config;
+// ways of copying bytes back
+pub mod echo;
+// HTTP echo
+pub mod http;
+// multiplexed virtual streams
+pub mod mux;
+// WebAssembly transform plugins
+pub mod plugin;
+// dropping privileges after binding
+pub mod privileges;
+// proof-of-existence anchoring
+pub mod poe;
+// QUIC echo
+pub mod quic;
+// layout of runtimes and threads
+pub mod runtime;
+// accepting and echoing connections
+pub mod server;
+// socket tuning
+pub mod socket;
+// SOCKS5 proxy
+pub mod socks;
+// zero-copy echo
+#[cfg(target_os = "linux")]
+mod splice;
+// statistics per client
+pub mod stats;
+// systemd integration
+pub mod systemd;
+// session transcript hashing
+pub mod transcript;
+// io_uring backend
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+
+pub use config::Config;
+pub use server::{handle_client, serve, Server, Summary};
@@ -1,91 +1,217 @@
 // Homework requires all statements to be commented
 
 // use tokio for async runtime
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
 // use anyhow for error handling
 use anyhow::{bail, Context, Result};
 
-// type for socket address
-use std::net::SocketAddr;
-
-const BUFFER_SIZE: usize = 1024;
-
-/// handle a TCP stream from client
-async fn handle_client(mut socket: TcpStream, client_address: SocketAddr) -> Result<u64> {
-    // buffer for incoming message
-    let mut buffer = [0u8; BUFFER_SIZE];
-    // split socket
-    let (mut reader, mut writer) = socket.split();
-    // total count of received bytes
-    let mut echoed_bytes_count: u64 = 0;
-
-    // loop to handle data
-    loop {
-        match reader.read(&mut buffer).await {
-            // connection closed
-            Ok(0) => return Ok(echoed_bytes_count),
-            // receive message
-            Ok(n) => {
-                // add to sum
-                echoed_bytes_count += n as u64;
-
-                // print to screen
-                println!(
-                    "From {:?}: {}",
-                    client_address,
-                    String::from_utf8_lossy(&buffer)
-                );
-
-                // echo
-                writer
-                    .write(&buffer)
-                    .await
-                    .context("Failed to write to client")?;
-            }
-            Err(err) => bail!("Failed to echo from {:?}: {}", client_address, err),
+// use clap to read options from command line
+use clap::Parser;
+
+// types for sharing options between tasks and timing
+use std::sync::Arc;
+use std::time::Duration;
+
+// the server itself
+use substrate_course_task_2::client;
+use substrate_course_task_2::config::Action;
+use substrate_course_task_2::privileges::Needs;
+use substrate_course_task_2::quic;
+use substrate_course_task_2::runtime;
+use substrate_course_task_2::{admin, serve, stats, systemd, Config, Server};
+
+/// wait for Ctrl-C or `terminate`
+async fn shutdown_signal(mut terminate: Signal) -> Result<()> {
+    // whichever comes first
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.context("Failed to listen for Ctrl-C"),
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+fn main() -> Result<()> {
+    // read options from command line
+    let mut config = Config::parse();
+
+    // run a tool instead when asked to
+    if let Some(Action::Stats { client }) = config.action {
+        let path = config
+            .stats_file
+            .context("--stats-file is required to show statistics")?;
+        return stats::print(&path, client);
+    }
+    if let Some(Action::Client(options)) = &config.action {
+        // one connection is all the client needs
+        let runtime = config.runtime.build()?;
+        let report = runtime.block_on(client::run(
+            config.checksum,
+            config.checksum_mode,
+            options,
+            config.quiet,
+        ))?;
+        println!(
+            "{} frames ({} bytes) checked, {} corrupted ({} upstream, {} downstream)",
+            report.frames, report.bytes, report.corrupted, report.upstream, report.downstream
+        );
+        if report.corrupted > 0 {
+            bail!("Corrupted frames detected");
         }
+        return Ok(());
+    }
+
+    // running as root is refused before anything is bound
+    config.privileges.check()?;
+
+    // threads of thread-per-core each bind the same port
+    if config.runtime.runtime.per_core() {
+        config.socket.reuse_port = true;
     }
+
+    // use the sockets passed by systemd, if any, while the environment can still be changed
+    let listeners = systemd::listen_fds().context("Failed to use sockets from systemd")?;
+
+    // start the runtime asked for and run the server on it
+    config.runtime.build()?.block_on(run(config, listeners))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // initialize a TCP socket server
-    let listener = TcpListener::bind("0.0.0.0:8080")
-        .await
-        .context("Failed to initialize TCP server")?;
-
-    // accept connections and process them, spawning a new thread for each one
-    println!("Server listening on port 8080");
-
-    loop {
-        // try to accept an incoming connection
-        match listener.accept().await {
-            // when connection established
-            Ok((socket, client_address)) => {
-                // spawn a new task to handle this connection
-                tokio::spawn(async move {
-                    println!("New connection from {:?}", client_address);
-                    match handle_client(socket, client_address).await {
-                        // connection closed
-                        Ok(n) => {
-                            println!(
-                                "Connection from {:?} closed, with {} bytes echoed",
-                                client_address, n
-                            );
-                        }
-                        // error happened
-                        Err(e) => {
-                            println!("{}", e);
-                        }
-                    }
-                });
-            }
-            // when connection failed to be established
-            Err(e) => {
-                println!("Failed to establish a connection: {}", e);
-            }
+/// run the server on `listeners`, or on a socket of its own, until asked to stop
+async fn run(config: Config, mut listeners: Vec<std::net::TcpListener>) -> Result<()> {
+    // systemd stops services with SIGTERM, possibly as soon as we are listening
+    let terminate = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM")?;
+
+    // otherwise initialize a TCP socket server
+    if listeners.is_empty() {
+        listeners.push(
+            config
+                .socket
+                .bind(config.listen)
+                .context("Failed to initialize TCP server")?,
+        );
+    }
+
+    // state shared by all connections
+    let server = Arc::new(Server::new(config)?);
+
+    // threads of their own per core, with sockets bound now while still privileged
+    let per_core = server.config.runtime.runtime.per_core();
+    let cores = server.config.runtime.cores();
+    let listeners = if per_core {
+        runtime::listeners_per_core(&server, listeners, &cores)?
+    } else {
+        vec![listeners]
+    };
+
+    // QUIC next to TCP
+    let endpoint = match server.config.quic {
+        Some(address) => {
+            // certificate from files, or made up for local testing
+            let identity = match (&server.config.quic_cert, &server.config.quic_key) {
+                (Some(certificate), Some(key)) => quic::Identity::load_or_create(certificate, key)?,
+                _ => quic::Identity::self_signed()?,
+            };
+            // authorities of client certificates, if any
+            let client_authorities = match &server.config.quic_client_ca {
+                Some(path) => quic::load_certificates(path)?,
+                None => Vec::new(),
+            };
+            Some(quic::endpoint(address, identity, &client_authorities)?)
+        }
+        None => None,
+    };
+
+    // administration next to the server
+    let admin = match server.config.admin {
+        Some(address) => Some(
+            std::net::TcpListener::bind(address)
+                .with_context(|| format!("Failed to listen for administrators on {}", address))?,
+        ),
+        None => None,
+    };
+
+    // everything is bound, give up what is not needed anymore
+    let config = &server.config;
+    let needs = Needs {
+        io_uring: config.runtime.runtime == runtime::Flavor::IoUring,
+        files: config.stats_file.is_some()
+            || config.capture.is_some()
+            || config.audit_log.is_some(),
+        connect: config.poe_node.is_some() || config.socks_upstream,
+    };
+    config.privileges.apply(needs)?;
+
+    if per_core {
+        // a runtime per core, this one is left with housekeeping
+        runtime::spawn_per_core(server.clone(), listeners, &cores)?;
+    } else {
+        for listener in listeners.into_iter().flatten() {
+            // hand the socket over to tokio
+            let listener =
+                TcpListener::from_std(listener).context("Failed to initialize TCP server")?;
+            println!("Server listening on {}", listener.local_addr()?);
+
+            // serve it in the background
+            tokio::spawn(serve(listener, server.clone()));
         }
     }
+
+    if let Some(endpoint) = endpoint {
+        println!("Server listening on {} (QUIC)", endpoint.local_addr()?);
+
+        // serve it in the background
+        tokio::spawn(quic::serve(endpoint, server.clone()));
+    }
+
+    if let (Some(listener), Some(audit)) = (admin, &server.audit) {
+        // hand the socket over to tokio
+        listener
+            .set_nonblocking(true)
+            .context("Failed to initialize admin interface")?;
+        let listener =
+            TcpListener::from_std(listener).context("Failed to initialize admin interface")?;
+        println!("Admin interface listening on {}", listener.local_addr()?);
+
+        // serve it in the background
+        tokio::spawn(admin::serve(listener, audit.clone()));
+    }
+
+    // save statistics regularly
+    if let Some(path) = &server.config.stats_file {
+        let interval = Duration::from_secs(server.config.stats_interval);
+        tokio::spawn(stats::persist(server.stats.clone(), path.clone(), interval));
+    }
+
+    // tell systemd we are up
+    if let Err(e) = systemd::notify("READY=1") {
+        println!("{:#}", e);
+    }
+    // keep systemd's watchdog happy
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(systemd::watchdog(interval));
+    }
+
+    // run until asked to stop
+    shutdown_signal(terminate).await?;
+    println!("Server shutting down");
+
+    // keep the latest statistics
+    if let Some(path) = &server.config.stats_file {
+        server.stats.save(path)?;
+    }
+    // connections still open end here
+    if let Some(audit) = &server.audit {
+        audit.shutdown();
+    }
+    // everything captured so far goes to the file
+    if let Some(capture) = &server.capture {
+        capture.flush();
+    }
+
+    // tell systemd we are going away
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        println!("{:#}", e);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,324 @@
+// multiplexed virtual streams over one connection, each echoed on its own
+//
+// every frame is a 9-byte header, then the payload of DATA frames:
+//
+//   type       u8    0 OPEN, 1 DATA, 2 CLOSE, 3 WINDOW_UPDATE
+//   stream id  u32   big endian
+//   length     u32   big endian, payload length of DATA, increment of WINDOW_UPDATE, else 0
+//
+// the server mirrors the client: OPEN is answered with OPEN, DATA is echoed as DATA and
+// CLOSE is answered with CLOSE once everything received on the stream was echoed
+//
+// each side may send at most the window (--mux-window) of DATA per stream before the
+// other grants more with WINDOW_UPDATE; the server grants what its echo wrote to the socket,
+// so one stalled stream never holds up the others, and a client that does not read its
+// echo cannot make the server buffer more than a window per stream
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// types for I/O results, socket address, streams by id and credit to grant
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+
+/// size of a frame header
+pub const HEADER_SIZE: usize = 9;
+
+/// stop reading from the client while this much output waits for the socket
+const MAX_OUTPUT: usize = 256 * 1024;
+
+/// frame types
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const CLOSE: u8 = 2;
+const WINDOW_UPDATE: u8 = 3;
+
+/// one frame of the protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// start a stream
+    Open(u32),
+    /// bytes on a stream
+    Data(u32, Vec<u8>),
+    /// no more data from the sender on a stream
+    Close(u32),
+    /// the sender may send this many more bytes on a stream
+    WindowUpdate(u32, u32),
+}
+
+impl Frame {
+    /// append the encoded frame to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        // header fields
+        let (kind, id, length) = match self {
+            Frame::Open(id) => (OPEN, *id, 0),
+            Frame::Data(id, data) => (DATA, *id, data.len() as u32),
+            Frame::Close(id) => (CLOSE, *id, 0),
+            Frame::WindowUpdate(id, increment) => (WINDOW_UPDATE, *id, *increment),
+        };
+        out.push(kind);
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(&length.to_be_bytes());
+        // payload
+        if let Frame::Data(_, data) = self {
+            out.extend_from_slice(data);
+        }
+    }
+
+    /// parse a frame from the start of `buffer`, with its encoded length,
+    /// or `None` when more bytes are needed; DATA longer than `max_data` is refused
+    pub fn parse(buffer: &[u8], max_data: u32) -> io::Result<Option<(Frame, usize)>> {
+        // whole header first
+        if buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let kind = buffer[0];
+        let id = u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]);
+        let length = u32::from_be_bytes([buffer[5], buffer[6], buffer[7], buffer[8]]);
+
+        let frame = match kind {
+            OPEN | CLOSE if length != 0 => return Err(invalid("OPEN and CLOSE carry no payload")),
+            OPEN => Frame::Open(id),
+            CLOSE => Frame::Close(id),
+            WINDOW_UPDATE => Frame::WindowUpdate(id, length),
+            DATA => {
+                // never buffer more than a window
+                if length > max_data {
+                    return Err(invalid("DATA frame exceeds the window"));
+                }
+                // then the whole payload
+                let end = HEADER_SIZE + length as usize;
+                match buffer.get(HEADER_SIZE..end) {
+                    Some(data) => return Ok(Some((Frame::Data(id, data.to_vec()), end))),
+                    None => return Ok(None),
+                }
+            }
+            _ => return Err(invalid("unknown frame type")),
+        };
+
+        Ok(Some((frame, HEADER_SIZE)))
+    }
+}
+
+/// a protocol violation
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// one virtual stream
+struct Stream {
+    /// bytes the client still lets us send
+    send_window: u64,
+    /// received bytes not echoed yet
+    pending: Vec<u8>,
+    /// echoed bytes not granted back yet, as they did not reach the socket
+    unacked: u64,
+    /// the client closed its side
+    closing: bool,
+    /// bytes received so far
+    received: u64,
+}
+
+/// streams of one connection
+struct Session {
+    /// initial window of each direction
+    window: u32,
+    /// streams open at the same time, at most
+    max_streams: usize,
+    streams: BTreeMap<u32, Stream>,
+    /// bytes received over all streams
+    received: u64,
+    /// bytes written to the socket so far
+    written: u64,
+    /// echo to grant back once written reaches the offset: offset, stream, bytes
+    credits: VecDeque<(u64, u32, u32)>,
+}
+
+impl Session {
+    /// act on a frame from the client
+    fn handle(&mut self, frame: Frame, output: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            Frame::Open(id) => {
+                if self.streams.contains_key(&id) {
+                    return Err(invalid("stream is already open"));
+                }
+                if self.streams.len() >= self.max_streams {
+                    return Err(io::Error::new(
+                        io::ErrorKind::QuotaExceeded,
+                        "too many open streams",
+                    ));
+                }
+                self.streams.insert(
+                    id,
+                    Stream {
+                        send_window: self.window as u64,
+                        pending: Vec::new(),
+                        unacked: 0,
+                        closing: false,
+                        received: 0,
+                    },
+                );
+                // accept it
+                Frame::Open(id).encode(output);
+            }
+            Frame::Data(id, data) => {
+                let stream = self
+                    .streams
+                    .get_mut(&id)
+                    .ok_or_else(|| invalid("DATA on a stream that is not open"))?;
+                if stream.closing {
+                    return Err(invalid("DATA after CLOSE"));
+                }
+                // the client may only send what was granted
+                let outstanding = stream.pending.len() as u64 + stream.unacked;
+                if outstanding + data.len() as u64 > self.window as u64 {
+                    return Err(invalid("DATA exceeds the window"));
+                }
+                stream.received += data.len() as u64;
+                self.received += data.len() as u64;
+                stream.pending.extend_from_slice(&data);
+            }
+            Frame::Close(id) => {
+                let stream = self
+                    .streams
+                    .get_mut(&id)
+                    .ok_or_else(|| invalid("CLOSE on a stream that is not open"))?;
+                stream.closing = true;
+            }
+            Frame::WindowUpdate(id, increment) => {
+                // the stream may have been closed meanwhile
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.send_window += increment as u64;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// echo what the windows allow and close finished streams, returning them with their byte counts
+    fn flush(&mut self, output: &mut Vec<u8>) -> Vec<(u32, u64)> {
+        let mut closed = Vec::new();
+
+        for (&id, stream) in self.streams.iter_mut() {
+            // as much as the client accepts
+            let n = (stream.pending.len() as u64).min(stream.send_window) as usize;
+            if n > 0 {
+                let data: Vec<u8> = stream.pending.drain(..n).collect();
+                Frame::Data(id, data).encode(output);
+                stream.send_window -= n as u64;
+                // the client may send that much again once it is written
+                stream.unacked += n as u64;
+                let offset = self.written + output.len() as u64;
+                self.credits.push_back((offset, id, n as u32));
+            }
+            // everything echoed and granted back
+            if stream.closing && stream.pending.is_empty() && stream.unacked == 0 {
+                Frame::Close(id).encode(output);
+                closed.push((id, stream.received));
+            }
+        }
+        for (id, _) in &closed {
+            self.streams.remove(id);
+        }
+
+        closed
+    }
+
+    /// account for `n` bytes of output written, granting back the echo they completed
+    fn sent(&mut self, n: usize, output: &mut Vec<u8>) {
+        self.written += n as u64;
+        while let Some(&(offset, id, length)) = self.credits.front() {
+            if offset > self.written {
+                break;
+            }
+            self.credits.pop_front();
+            // streams wait for their credit before closing
+            if let Some(stream) = self.streams.get_mut(&id) {
+                stream.unacked -= length as u64;
+                Frame::WindowUpdate(id, length).encode(output);
+            }
+        }
+    }
+}
+
+/// serve virtual streams until the client shuts down its write half,
+/// returning the count of bytes received over all streams
+pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    window: u32,
+    max_streams: u32,
+    quiet: bool,
+) -> io::Result<u64> {
+    // read and write at the same time, so a client that is busy writing never blocks us
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    // bytes read but not parsed, and bytes to write
+    let mut input: Vec<u8> = Vec::with_capacity(64 * 1024);
+    let mut output: Vec<u8> = Vec::new();
+    let mut finished_reading = false;
+    let mut session = Session {
+        window,
+        max_streams: max_streams as usize,
+        streams: BTreeMap::new(),
+        received: 0,
+        written: 0,
+        credits: VecDeque::new(),
+    };
+
+    loop {
+        // act on every complete frame
+        let mut parsed = 0;
+        while let Some((frame, length)) = Frame::parse(&input[parsed..], window)? {
+            parsed += length;
+            if !quiet {
+                if let Frame::Open(id) = frame {
+                    println!("Stream {} from {:?} opened", id, client_address);
+                }
+            }
+            session.handle(frame, &mut output)?;
+        }
+        input.drain(..parsed);
+
+        // echo what can be echoed
+        for (id, received) in session.flush(&mut output) {
+            if !quiet {
+                println!(
+                    "Stream {} from {:?} closed, with {} bytes echoed",
+                    id, client_address, received
+                );
+            }
+        }
+
+        // nothing more will come in, and everything possible went out
+        if finished_reading && output.is_empty() {
+            if !input.is_empty() {
+                return Err(invalid("connection ended within a frame"));
+            }
+            return Ok(session.received);
+        }
+
+        // whichever is ready first, both are cancel safe; a client not reading
+        // its echo is not read from either
+        if input.capacity() - input.len() < HEADER_SIZE {
+            input.reserve(64 * 1024);
+        }
+        let reading = !finished_reading && output.len() < MAX_OUTPUT;
+        tokio::select! {
+            n = reader.read_buf(&mut input), if reading => {
+                finished_reading = n? == 0;
+            }
+            n = writer.write(&output), if !output.is_empty() => {
+                // a writer taking nothing would be written to forever
+                let n = match n? {
+                    0 => return Err(io::ErrorKind::WriteZero.into()),
+                    n => n,
+                };
+                output.drain(..n);
+                writer.flush().await?;
+                session.sent(n, &mut output);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// WebAssembly plugins deciding what is sent back
+//
+// a plugin is a core module, in binary or text format and without imports, exporting:
+//
+//   memory                                 its linear memory
+//   alloc(len: i32) -> i32                 room for an input of `len` bytes
+//   transform(ptr: i32, len: i32) -> i64   bytes to send back for an input, as ptr << 32 | len
+//   finish() -> i64                        optional, bytes to send once the client is done
+//
+// every connection gets its own instance, so globals and memory hold per-connection
+// state, within --wasm-memory-limit bytes of memory and --wasm-fuel units of fuel; plugins
+// run on the blocking threads of the runtime, so a slow one holds up no other connection
+
+// use wasmtime to run plugins
+use wasmtime::{
+    Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap, TypedFunc,
+};
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// use anyhow for error handling
+use anyhow::{anyhow, Context, Result};
+
+// types for conversions, I/O results, socket address and file paths
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// a compiled plugin with the limits of each connection
+pub struct Plugin {
+    engine: Engine,
+    module: Module,
+    /// bytes of linear memory an instance may grow to
+    memory_limit: usize,
+    /// fuel an instance may burn over its whole connection
+    fuel: u64,
+}
+
+/// the instance of a plugin serving one connection
+pub struct Transformer {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32), i64>,
+    finish: Option<TypedFunc<(), i64>>,
+}
+
+impl Plugin {
+    /// compile the module in `path`, checking it can be instantiated within the limits
+    pub fn load(path: &Path, memory_limit: usize, fuel: u64) -> Result<Self> {
+        // count fuel so runaway plugins are stopped
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).context("Failed to set up WebAssembly engine")?;
+        let module = Module::from_file(&engine, path)
+            .with_context(|| format!("Failed to load plugin {}", path.display()))?;
+
+        let plugin = Plugin {
+            engine,
+            module,
+            memory_limit,
+            fuel,
+        };
+        // fail now rather than on every connection
+        plugin
+            .instantiate()
+            .with_context(|| format!("Invalid plugin {}", path.display()))?;
+
+        Ok(plugin)
+    }
+
+    /// a fresh instance for one connection
+    pub fn instantiate(&self) -> Result<Transformer> {
+        // limits of this connection
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.memory_limit)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel)?;
+
+        // no imports, everything comes from the module
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("plugin does not export its memory"))?;
+        let alloc = instance.get_typed_func(&mut store, "alloc")?;
+        let transform = instance.get_typed_func(&mut store, "transform")?;
+        let finish = match instance.get_export(&mut store, "finish") {
+            Some(_) => Some(instance.get_typed_func(&mut store, "finish")?),
+            None => None,
+        };
+
+        Ok(Transformer {
+            store,
+            memory,
+            alloc,
+            transform,
+            finish,
+        })
+    }
+}
+
+impl Transformer {
+    /// bytes to send back for `input`
+    pub fn transform(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        // copy the input into the plugin
+        let length = i32::try_from(input.len()).context("input too large for plugin")?;
+        let pointer = self.alloc.call(&mut self.store, length)?;
+        self.memory
+            .data_mut(&mut self.store)
+            .get_mut(pointer as u32 as usize..)
+            .and_then(|memory| memory.get_mut(..input.len()))
+            .ok_or_else(|| anyhow!("plugin allocated outside its memory"))?
+            .copy_from_slice(input);
+
+        let output = self.transform.call(&mut self.store, (pointer, length))?;
+        self.output(output)
+    }
+
+    /// bytes to send back once the client is done
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        match self.finish.clone() {
+            Some(finish) => {
+                let output = finish.call(&mut self.store, ())?;
+                self.output(output)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// copy out the bytes at ptr << 32 | len
+    fn output(&self, output: i64) -> Result<Vec<u8>> {
+        let pointer = (output as u64 >> 32) as usize;
+        let length = (output as u64 & 0xffff_ffff) as usize;
+        self.memory
+            .data(&self.store)
+            .get(pointer..)
+            .and_then(|memory| memory.get(..length))
+            .map(|bytes| bytes.to_vec())
+            .ok_or_else(|| anyhow!("plugin returned bytes outside its memory"))
+    }
+}
+
+/// a plugin failure, ending the connection
+fn failed(e: anyhow::Error) -> io::Error {
+    // running out of fuel is the connection hitting its limit
+    let kind = match e.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => io::ErrorKind::QuotaExceeded,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("plugin failed: {:#}", e))
+}
+
+/// run `call` on `transformer` on a blocking thread, handing the instance back with its result
+async fn off_runtime<F>(mut transformer: Transformer, call: F) -> io::Result<(Transformer, Vec<u8>)>
+where
+    F: FnOnce(&mut Transformer) -> Result<Vec<u8>> + Send + 'static,
+{
+    let (transformer, output) = tokio::task::spawn_blocking(move || {
+        let output = call(&mut transformer);
+        (transformer, output)
+    })
+    .await
+    .map_err(|e| io::Error::other(format!("plugin failed: {}", e)))?;
+
+    Ok((transformer, output.map_err(failed)?))
+}
+
+/// send back what the plugin makes of each chunk, until the client shuts down its write half
+pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    plugin: &Plugin,
+    buffer_size: usize,
+    quiet: bool,
+) -> io::Result<u64> {
+    // per-connection state lives in the instance
+    let mut transformer = plugin.instantiate().map_err(failed)?;
+    // buffer for incoming chunks
+    let mut buffer = vec![0u8; buffer_size];
+    // total count of received bytes
+    let mut received: u64 = 0;
+
+    loop {
+        // client finished sending
+        let n = stream.read(&mut buffer).await?;
+        let (back, output) = if n == 0 {
+            off_runtime(transformer, |transformer| transformer.finish()).await?
+        } else {
+            received += n as u64;
+            let chunk = buffer[..n].to_vec();
+            off_runtime(transformer, move |transformer| {
+                transformer.transform(&chunk)
+            })
+            .await?
+        };
+        transformer = back;
+
+        // print to screen
+        if !quiet {
+            println!(
+                "From {:?}: {} bytes, answered with {}",
+                client_address,
+                n,
+                output.len()
+            );
+        }
+
+        // may be nothing at all
+        stream.write_all(&output).await?;
+        stream.flush().await?;
+
+        if n == 0 {
+            return Ok(received);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+// anchoring of session transcripts as proof-of-existence claims
+//
+// the transcript hash is submitted as `poe::create_claim` (see ../poe-chain) in a
+// signed extrinsic through a node's HTTP JSON-RPC endpoint
+
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+// use schnorrkel for sr25519 signatures
+use schnorrkel::{ExpansionMode, Keypair, MiniSecretKey};
+
+// use blake2 for hashing
+use blake2::digest::consts::U64;
+use blake2::{Blake2b, Digest};
+
+// use serde_json for JSON-RPC
+use serde_json::{json, Value};
+
+// use anyhow for error handling
+use anyhow::{anyhow, bail, Context, Result};
+
+// types for conversions and futures
+use std::convert::TryInto;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+// BLAKE2b-256
+use crate::transcript::Blake2b256;
+
+/// mini secret key of the `//Alice` development account
+pub const ALICE_SEED: &str = "e5be9a5092b81bca64be81d212e7f2f9eba183bb7a90954f7b76361f6edb5c0a";
+
+/// generic Substrate SS58 address prefix
+const SS58_PREFIX: u8 = 42;
+
+/// future returned by submitters
+pub type Submission<'a> = Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>;
+
+/// something that records a claim, returning an identifier of the record
+pub trait Submitter: Send + Sync {
+    /// submit `claim`, resolving to the hash of the submission
+    fn submit(&self, claim: [u8; 32]) -> Submission<'_>;
+}
+
+/// where `create_claim` lives in the runtime
+#[derive(Debug, Clone, Copy)]
+pub struct CallIndex {
+    /// position of the pallet in `construct_runtime!`
+    pub pallet: u8,
+    /// position of the call in the pallet
+    pub call: u8,
+}
+
+/// chain constants needed to sign, fetched once
+#[derive(Debug, Clone, Copy)]
+struct Chain {
+    genesis_hash: [u8; 32],
+    spec_version: u32,
+    transaction_version: u32,
+}
+
+/// submits claims to a node over HTTP JSON-RPC
+pub struct NodeSubmitter {
+    /// `host:port` of the node
+    address: String,
+    /// path of the RPC endpoint
+    path: String,
+    signer: Keypair,
+    call_index: CallIndex,
+    /// longest a JSON-RPC call may take, so a hung node holds up no submission for good
+    timeout: Duration,
+    /// chain constants, and a lock serializing submissions so nonces do not collide
+    chain: Mutex<Option<Chain>>,
+}
+
+impl NodeSubmitter {
+    /// submit to the node at `url` (`http://host:port/path`), signing with the sr25519 mini secret `seed`,
+    /// giving up on calls taking longer than `timeout`
+    pub fn new(url: &str, seed: &[u8], call_index: CallIndex, timeout: Duration) -> Result<Self> {
+        // only plain HTTP
+        let rest = url
+            .strip_prefix("http://")
+            .context("PoE node URL must start with http://")?;
+        let (address, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        // sr25519 keys are derived as Substrate does
+        let signer = MiniSecretKey::from_bytes(seed)
+            .map_err(|e| anyhow!("Invalid PoE signing seed: {}", e))?
+            .expand_to_keypair(ExpansionMode::Ed25519);
+
+        Ok(NodeSubmitter {
+            address: address.to_string(),
+            path: path.to_string(),
+            signer,
+            call_index,
+            timeout,
+            chain: Mutex::new(None),
+        })
+    }
+
+    /// SS58 address of the signing account
+    pub fn account(&self) -> String {
+        ss58(&self.signer.public.to_bytes())
+    }
+
+    /// call a JSON-RPC method, within the timeout
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        tokio::time::timeout(self.timeout, self.call_unbounded(method, params))
+            .await
+            .with_context(|| format!("{} timed out on PoE node {}", method, self.address))?
+    }
+
+    /// call a JSON-RPC method, however long the node takes
+    async fn call_unbounded(&self, method: &str, params: Value) -> Result<Value> {
+        // request
+        let body =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.address,
+            body.len(),
+            body
+        );
+
+        // one connection per call
+        let mut stream = TcpStream::connect(&self.address)
+            .await
+            .with_context(|| format!("Failed to connect to PoE node {}", self.address))?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        // status and body
+        let body = http_body(&response)?;
+        let response: Value = serde_json::from_slice(&body).context("Invalid JSON-RPC response")?;
+        if let Some(error) = response.get("error") {
+            bail!("{} failed: {}", method, error);
+        }
+
+        response
+            .get("result")
+            .cloned()
+            .with_context(|| format!("{} returned no result", method))
+    }
+
+    /// chain constants, fetched on first use
+    async fn chain(&self, cached: &mut Option<Chain>) -> Result<Chain> {
+        if let Some(chain) = *cached {
+            return Ok(chain);
+        }
+
+        // hash of block 0
+        let genesis_hash = self.call("chain_getBlockHash", json!([0])).await?;
+        let genesis_hash = decode_hex(genesis_hash.as_str().unwrap_or_default())?;
+        // versions checked by the runtime
+        let version = self.call("state_getRuntimeVersion", json!([])).await?;
+
+        let chain = Chain {
+            genesis_hash: genesis_hash
+                .try_into()
+                .map_err(|_| anyhow!("Invalid genesis hash"))?,
+            spec_version: version["specVersion"].as_u64().context("No spec version")? as u32,
+            transaction_version: version["transactionVersion"]
+                .as_u64()
+                .context("No transaction version")? as u32,
+        };
+        *cached = Some(chain);
+
+        Ok(chain)
+    }
+
+    /// build, sign and send the extrinsic
+    async fn submit_claim(&self, claim: [u8; 32]) -> Result<String> {
+        // one submission at a time
+        let mut cached = self.chain.lock().await;
+        let chain = self.chain(&mut cached).await?;
+
+        // next nonce, including pending transactions
+        let nonce = self
+            .call("system_accountNextIndex", json!([self.account()]))
+            .await?
+            .as_u64()
+            .context("Invalid nonce")? as u32;
+
+        // sign and submit
+        let extrinsic = signed_extrinsic(&self.signer, self.call_index, &chain, nonce, &claim);
+        let hash = self
+            .call(
+                "author_submitExtrinsic",
+                json!([format!("0x{}", hex::encode(extrinsic))]),
+            )
+            .await?;
+
+        Ok(hash.as_str().unwrap_or_default().to_string())
+    }
+}
+
+impl Submitter for NodeSubmitter {
+    fn submit(&self, claim: [u8; 32]) -> Submission<'_> {
+        Box::pin(self.submit_claim(claim))
+    }
+}
+
+/// SCALE compact encoding of an integer
+pub fn compact(value: u64, out: &mut Vec<u8>) {
+    match value {
+        0..=0x3f => out.push((value as u8) << 2),
+        0x40..=0x3fff => out.extend_from_slice(&(((value as u16) << 2) | 0b01).to_le_bytes()),
+        0x4000..=0x3fff_ffff => {
+            out.extend_from_slice(&(((value as u32) << 2) | 0b10).to_le_bytes())
+        }
+        _ => {
+            // big integer mode: length prefix, then little endian bytes
+            let bytes = value.to_le_bytes();
+            let length = 8 - value.leading_zeros() as usize / 8;
+            out.push((((length - 4) as u8) << 2) | 0b11);
+            out.extend_from_slice(&bytes[..length]);
+        }
+    }
+}
+
+/// encode a `create_claim(proof)` call
+pub fn create_claim_call(call_index: CallIndex, proof: &[u8]) -> Vec<u8> {
+    // pallet and call
+    let mut call = vec![call_index.pallet, call_index.call];
+    // Vec<u8> argument
+    compact(proof.len() as u64, &mut call);
+    call.extend_from_slice(proof);
+
+    call
+}
+
+/// encode a signed extrinsic for the runtime of ../poe-chain
+///
+/// signed extensions: CheckSpecVersion, CheckTxVersion, CheckGenesis, CheckEra (immortal),
+/// CheckNonce, CheckWeight and ChargeTransactionPayment (no tip)
+fn signed_extrinsic(
+    signer: &Keypair,
+    call_index: CallIndex,
+    chain: &Chain,
+    nonce: u32,
+    proof: &[u8],
+) -> Vec<u8> {
+    // the call
+    let call = create_claim_call(call_index, proof);
+    // extra data sent along: immortal era, nonce, tip
+    let mut extra = vec![0u8];
+    compact(nonce as u64, &mut extra);
+    compact(0, &mut extra);
+    // extra data only signed: versions, genesis hash and hash of the era's block
+    let mut additional = Vec::new();
+    additional.extend_from_slice(&chain.spec_version.to_le_bytes());
+    additional.extend_from_slice(&chain.transaction_version.to_le_bytes());
+    additional.extend_from_slice(&chain.genesis_hash);
+    additional.extend_from_slice(&chain.genesis_hash);
+
+    // long payloads are signed by hash
+    let payload = [&call[..], &extra, &additional].concat();
+    let payload = if payload.len() > 256 {
+        Blake2b256::digest(&payload).to_vec()
+    } else {
+        payload
+    };
+    let signature = signer.sign_simple(b"substrate", &payload);
+
+    // version 4, signed
+    let mut body = vec![0x84];
+    // MultiAddress::Id
+    body.push(0x00);
+    body.extend_from_slice(&signer.public.to_bytes());
+    // MultiSignature::Sr25519
+    body.push(0x01);
+    body.extend_from_slice(&signature.to_bytes());
+    body.extend_from_slice(&extra);
+    body.extend_from_slice(&call);
+
+    // length prefixed
+    let mut extrinsic = Vec::new();
+    compact(body.len() as u64, &mut extrinsic);
+    extrinsic.extend_from_slice(&body);
+
+    extrinsic
+}
+
+/// SS58 address of a public key with the generic Substrate prefix
+pub fn ss58(public: &[u8; 32]) -> String {
+    // prefix and key
+    let mut address = vec![SS58_PREFIX];
+    address.extend_from_slice(public);
+    // checksum over a fixed preamble
+    let checksum = Blake2b::<U64>::new()
+        .chain_update(b"SS58PRE")
+        .chain_update(&address)
+        .finalize();
+    address.extend_from_slice(&checksum[..2]);
+
+    bs58::encode(address).into_string()
+}
+
+/// decode a `0x` prefixed hex string
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x")).context("Invalid hex from PoE node")
+}
+
+/// body of an HTTP response, decoding chunked transfer encoding
+fn http_body(response: &[u8]) -> Result<Vec<u8>> {
+    // status line and headers
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let length = match parsed.parse(response)? {
+        httparse::Status::Complete(length) => length,
+        httparse::Status::Partial => bail!("Incomplete HTTP response from PoE node"),
+    };
+    if parsed.code != Some(200) {
+        bail!("PoE node answered HTTP {}", parsed.code.unwrap_or_default());
+    }
+    let chunked = parsed.headers.iter().any(|header| {
+        header.name.eq_ignore_ascii_case("transfer-encoding")
+            && String::from_utf8_lossy(header.value).contains("chunked")
+    });
+    let mut body = &response[length..];
+    if !chunked {
+        return Ok(body.to_vec());
+    }
+
+    // chunk by chunk
+    let mut decoded = Vec::new();
+    loop {
+        let end = body
+            .windows(2)
+            .position(|pair| pair == b"\r\n")
+            .context("Invalid chunked response")?;
+        let size = crate::http::parse_chunk_size(&body[..end]).context("Invalid chunk size")?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        // huge sizes must not overflow
+        let start = end + 2;
+        let stop = start.checked_add(size).context("Invalid chunk size")?;
+        let chunk = body
+            .get(start..stop)
+            .context("Truncated chunked response")?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(stop + 2..).unwrap_or_default();
+    }
+}
@@ -0,0 +1,421 @@
+// running unprivileged once every socket is bound
+//
+// in order: look up the user and group, chroot, switch group and user, then install the
+// seccomp filter; running as root without switching user is refused unless --allow-root
+
+// use anyhow for error handling
+use anyhow::{anyhow, bail, Context, Result};
+
+// use clap to read options from command line
+use clap::Args;
+
+// types for C strings, file paths and raw pointers
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// options dropping privileges after binding
+#[derive(Debug, Clone, Args)]
+pub struct PrivilegeOptions {
+    /// after binding, run as this user (name or uid)
+    #[arg(long, value_name = "USER")]
+    pub user: Option<String>,
+
+    /// after binding, run as this group (name or gid); the primary group of --user by default
+    #[arg(long, value_name = "GROUP")]
+    pub group: Option<String>,
+
+    /// after binding, confine the server to this directory
+    #[arg(long, value_name = "DIR")]
+    pub chroot: Option<PathBuf>,
+
+    /// after binding, only allow the system calls the server needs, killing it on any
+    /// other (Linux only)
+    #[arg(long)]
+    pub seccomp: bool,
+
+    /// keep running as root when no --user is given
+    #[arg(long)]
+    pub allow_root: bool,
+}
+
+/// what the server still does once privileges are dropped, each allowing more system calls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Needs {
+    /// echoing through io_uring
+    pub io_uring: bool,
+    /// creating, renaming and removing files: statistics, captures and audit logs
+    pub files: bool,
+    /// connecting out, resolving names on the way: the PoE node and SOCKS upstreams
+    pub connect: bool,
+}
+
+/// buffer size for getpwnam_r and getgrnam_r
+const LOOKUP_BUFFER_SIZE: usize = 16 * 1024;
+
+/// uid and primary gid of a user given by name or number
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    // passwd entry by name
+    let name = CString::new(user).context("Invalid user name")?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut found: *mut libc::passwd = ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+
+    if status == 0 && !found.is_null() {
+        return Ok((entry.pw_uid, entry.pw_gid));
+    }
+    // numbers may have no passwd entry, their primary group is then their own number
+    match user.parse() {
+        Ok(uid) => Ok((uid, uid)),
+        Err(_) => bail!("No such user: {}", user),
+    }
+}
+
+/// gid of a group given by name or number
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    // group entry by name
+    let name = CString::new(group).context("Invalid group name")?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut found: *mut libc::group = ptr::null_mut();
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+
+    if status == 0 && !found.is_null() {
+        return Ok(entry.gr_gid);
+    }
+    // or a number
+    group
+        .parse()
+        .map_err(|_| anyhow!("No such group: {}", group))
+}
+
+/// the last OS error, as an anyhow error
+fn os_error() -> anyhow::Error {
+    std::io::Error::last_os_error().into()
+}
+
+/// confine the process to `directory`
+fn chroot(directory: &Path) -> Result<()> {
+    // paths resolve inside from now on
+    let path = CString::new(directory.as_os_str().as_bytes()).context("Invalid directory")?;
+    if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+        return Err(os_error())
+            .with_context(|| format!("Failed to chroot to {}", directory.display()));
+    }
+    // the old working directory would stay reachable
+    let root = CStr::from_bytes_with_nul(b"/\0").expect("literal is a C string");
+    if unsafe { libc::chdir(root.as_ptr()) } != 0 {
+        return Err(os_error()).context("Failed to change directory to the new root");
+    }
+
+    Ok(())
+}
+
+/// switch to `uid` and `gid` for good, without supplementary groups
+fn switch_user(uid: libc::uid_t, gid: libc::gid_t) -> Result<()> {
+    // group first, it cannot be changed once the user is not root anymore
+    if unsafe { libc::setgroups(1, &gid) } != 0 {
+        return Err(os_error()).context("Failed to drop supplementary groups");
+    }
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(os_error()).with_context(|| format!("Failed to switch to group {}", gid));
+    }
+    if unsafe { libc::setuid(uid) } != 0 {
+        return Err(os_error()).with_context(|| format!("Failed to switch to user {}", uid));
+    }
+
+    // root could otherwise come back
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        bail!(
+            "Privileges could be regained after switching to user {}",
+            uid
+        );
+    }
+
+    Ok(())
+}
+
+impl PrivilegeOptions {
+    /// refuse to run as root without dropping privileges, before anything is bound
+    pub fn check(&self) -> Result<()> {
+        // effective uid, as setuid binaries would have it
+        if unsafe { libc::geteuid() } == 0 && self.user.is_none() && !self.allow_root {
+            bail!("Refusing to run as root: pass --user to drop privileges after binding, or --allow-root");
+        }
+
+        Ok(())
+    }
+
+    /// drop privileges as configured, once every socket and file the server needs is open;
+    /// `needs` tells which system calls the server still makes beyond echoing
+    pub fn apply(&self, needs: Needs) -> Result<()> {
+        // checked again for callers other than main
+        self.check()?;
+
+        // names are looked up before the passwd file goes out of reach
+        let user = self.user.as_deref().map(lookup_user).transpose()?;
+        let group = self.group.as_deref().map(lookup_group).transpose()?;
+
+        // while still root, chroot needs it
+        if let Some(directory) = &self.chroot {
+            chroot(directory)?;
+        }
+
+        // a user comes with its primary group unless told otherwise
+        match (user, group) {
+            (Some((uid, primary)), group) => switch_user(uid, group.unwrap_or(primary))?,
+            // a group alone only changes the group
+            (None, Some(gid)) => {
+                if unsafe { libc::setgid(gid) } != 0 {
+                    return Err(os_error())
+                        .with_context(|| format!("Failed to switch to group {}", gid));
+                }
+            }
+            (None, None) => {}
+        }
+
+        // last, the filter would forbid the calls above
+        if self.seccomp {
+            install_filter(needs)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// system calls made once running, whatever their arguments: echoing, accepting, threads,
+/// timers and WebAssembly plugins
+#[cfg(target_os = "linux")]
+const ALLOWED: &[libc::c_long] = &[
+    // sockets, created by `socket` with conditions below
+    libc::SYS_accept4,
+    libc::SYS_bind,
+    libc::SYS_getpeername,
+    libc::SYS_getsockname,
+    libc::SYS_getsockopt,
+    libc::SYS_listen,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_sendmsg,
+    libc::SYS_sendto,
+    libc::SYS_setsockopt,
+    libc::SYS_shutdown,
+    // reading, writing and zero-copy
+    libc::SYS_close,
+    libc::SYS_pipe2,
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_splice,
+    libc::SYS_write,
+    libc::SYS_writev,
+    // waiting for readiness
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_ppoll,
+    // memory
+    libc::SYS_brk,
+    libc::SYS_madvise,
+    libc::SYS_mmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_munmap,
+    // threads, time and signals; clone3 is answered ENOSYS by a second filter
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clone3,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_futex,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_nanosleep,
+    libc::SYS_rseq,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_setaffinity,
+    libc::SYS_sched_yield,
+    libc::SYS_set_robust_list,
+    libc::SYS_sigaltstack,
+    libc::SYS_tgkill,
+    // files already open, opened ones are below
+    libc::SYS_fcntl,
+    libc::SYS_fstat,
+    libc::SYS_fsync,
+    libc::SYS_ftruncate,
+    libc::SYS_getrandom,
+    libc::SYS_lseek,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_uname,
+    // older variants still used on x86_64
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+];
+
+/// system calls writing files: saving statistics, rotating captures and audit logs
+#[cfg(target_os = "linux")]
+const FILES: &[libc::c_long] = &[
+    libc::SYS_openat,
+    libc::SYS_renameat,
+    libc::SYS_unlinkat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+];
+
+/// system calls of the io_uring backend, which could do the work of any other
+#[cfg(target_os = "linux")]
+const IO_URING: &[libc::c_long] = &[
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+    libc::SYS_io_uring_setup,
+];
+
+/// system calls allowed only with some arguments, each condition being enough
+#[cfg(target_os = "linux")]
+fn conditional_rules(needs: Needs) -> Result<Vec<(libc::c_long, Vec<seccompiler::SeccompRule>)>> {
+    use seccompiler::{SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompRule};
+
+    // argument `index` equal to `value`, or with the `mask` bits of `value` set
+    let rule = |index, op, value: u64| -> Result<SeccompRule> {
+        let condition = SeccompCondition::new(index, SeccompCmpArgLen::Dword, op, value)
+            .context("Failed to build seccomp condition")?;
+        SeccompRule::new(vec![condition]).context("Failed to build seccomp rule")
+    };
+    let thread = libc::CLONE_THREAD as u64;
+    let access = libc::O_ACCMODE as u64;
+
+    let mut rules = vec![
+        // IP sockets, and Unix ones for systemd notifications
+        (
+            libc::SYS_socket,
+            vec![
+                rule(0, SeccompCmpOp::Eq, libc::AF_INET as u64)?,
+                rule(0, SeccompCmpOp::Eq, libc::AF_INET6 as u64)?,
+                rule(0, SeccompCmpOp::Eq, libc::AF_UNIX as u64)?,
+            ],
+        ),
+        // threads, never processes
+        (
+            libc::SYS_clone,
+            vec![rule(0, SeccompCmpOp::MaskedEq(thread), thread)?],
+        ),
+        // naming threads
+        (
+            libc::SYS_prctl,
+            vec![rule(0, SeccompCmpOp::Eq, libc::PR_SET_NAME as u64)?],
+        ),
+        // non-blocking and close-on-exec descriptors
+        (
+            libc::SYS_ioctl,
+            vec![
+                rule(1, SeccompCmpOp::Eq, libc::FIONBIO)?,
+                rule(1, SeccompCmpOp::Eq, libc::FIOCLEX)?,
+            ],
+        ),
+    ];
+
+    // resolving names reads /etc/hosts, /etc/resolv.conf and the like, writing files
+    // allows any opening already
+    if needs.connect && !needs.files {
+        let read_only = libc::O_RDONLY as u64;
+        rules.push((
+            libc::SYS_openat,
+            vec![rule(2, SeccompCmpOp::MaskedEq(access), read_only)?],
+        ));
+        #[cfg(target_arch = "x86_64")]
+        rules.push((
+            libc::SYS_open,
+            vec![rule(1, SeccompCmpOp::MaskedEq(access), read_only)?],
+        ));
+    }
+
+    Ok(rules)
+}
+
+/// allow only the system calls the server makes once running, in every thread,
+/// killing the process on any other
+#[cfg(target_os = "linux")]
+fn install_filter(needs: Needs) -> Result<()> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+
+    // calls allowed whatever their arguments, as far as the server needs them
+    let mut rules = ALLOWED
+        .iter()
+        .chain(IO_URING.iter().filter(|_| needs.io_uring))
+        .chain(FILES.iter().filter(|_| needs.files))
+        .chain([libc::SYS_connect].iter().filter(|_| needs.connect))
+        .map(|&call| (call, Vec::new()))
+        .collect::<BTreeMap<_, _>>();
+    // then those with conditions
+    rules.extend(conditional_rules(needs)?);
+    let arch = TargetArch::try_from(std::env::consts::ARCH)
+        .map_err(|e| anyhow!("seccomp is not supported here: {}", e))?;
+
+    // a compromised server is stopped rather than told no
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::KillProcess,
+        SeccompAction::Allow,
+        arch,
+    )
+    .context("Failed to build seccomp filter")?;
+    let program = BpfProgram::try_from(filter).context("Failed to compile seccomp filter")?;
+
+    // the flags of clone3 are out of reach of filters, so threads are created with clone:
+    // the C library falls back to it when clone3 is not implemented
+    let clone3 = SeccompFilter::new(
+        vec![(libc::SYS_clone3, Vec::new())].into_iter().collect(),
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::ENOSYS as u32),
+        arch,
+    )
+    .context("Failed to build seccomp filter")?;
+    let clone3 = BpfProgram::try_from(clone3).context("Failed to compile seccomp filter")?;
+
+    // the most restrictive answer of both filters wins, installing the other one being
+    // forbidden by the main one
+    seccompiler::apply_filter_all_threads(&clone3).context("Failed to install seccomp filter")?;
+    seccompiler::apply_filter_all_threads(&program).context("Failed to install seccomp filter")?;
+
+    Ok(())
+}
+
+/// allow only the system calls the server makes once running, in every thread
+#[cfg(not(target_os = "linux"))]
+fn install_filter(_needs: Needs) -> Result<()> {
+    bail!("--seccomp is only supported on Linux")
+}
@@ -0,0 +1,321 @@
+// QUIC echo: every bidirectional stream is served like a TCP connection
+//
+// clients must offer the `echo` ALPN protocol; without a certificate on the command
+// line, a self-signed one for localhost is generated. With authorities to check them
+// against, clients may present a certificate too, whose fingerprint is audited
+
+// use quinn for QUIC
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::rustls::pki_types::pem::PemObject;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use quinn::rustls::{self, server::WebPkiClientVerifier, RootCertStore};
+use quinn::{ConnectionError, Endpoint, RecvStream, SendStream};
+
+// use tokio for async runtime
+use tokio::io::Join;
+use tokio::task::JoinSet;
+
+// use sha2 for certificate fingerprints
+use sha2::{Digest, Sha256};
+
+// use anyhow for error handling
+use anyhow::{Context, Result};
+
+// types for conversions, addresses, file paths and sharing
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+// connection audit log
+use crate::audit::{CloseReason, Session, Transport};
+// accepting and echoing connections
+use crate::server::{anchor_claim, handle_connection, Connection, Server, Summary};
+
+/// ALPN protocol clients must offer
+pub const ALPN: &[u8] = b"echo";
+
+/// application error code of connections killed by an administrator
+const KILLED: u32 = 1;
+
+/// names a self-signed certificate is valid for
+const LOCAL_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+/// certificate chain and private key of the server
+pub struct Identity {
+    pub certificates: Vec<CertificateDer<'static>>,
+    pub key: PrivateKeyDer<'static>,
+}
+
+/// a new self-signed certificate for localhost
+fn generate() -> Result<rcgen::CertifiedKey<rcgen::KeyPair>> {
+    let names: Vec<String> = LOCAL_NAMES.iter().map(|name| name.to_string()).collect();
+    rcgen::generate_simple_self_signed(names).context("Failed to generate a certificate")
+}
+
+impl Identity {
+    /// a new self-signed certificate for localhost, kept in memory
+    pub fn self_signed() -> Result<Self> {
+        let generated = generate()?;
+
+        Ok(Identity {
+            certificates: vec![generated.cert.der().clone()],
+            key: PrivatePkcs8KeyDer::from(generated.signing_key.serialize_der()).into(),
+        })
+    }
+
+    /// read PEM files, generating a self-signed pair first when neither exists
+    pub fn load_or_create(certificate: &Path, key: &Path) -> Result<Self> {
+        // keep a generated pair so clients can trust it across restarts
+        if !certificate.exists() && !key.exists() {
+            let generated = generate()?;
+            std::fs::write(certificate, generated.cert.pem()).with_context(|| {
+                format!("Failed to write certificate to {}", certificate.display())
+            })?;
+            std::fs::write(key, generated.signing_key.serialize_pem())
+                .with_context(|| format!("Failed to write key to {}", key.display()))?;
+        }
+
+        // whole chain, then the key
+        let certificates = load_certificates(certificate)?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .with_context(|| format!("Failed to read private key from {}", key.display()))?;
+
+        Ok(Identity { certificates, key })
+    }
+}
+
+/// every certificate in a PEM file
+pub fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))
+}
+
+/// a QUIC endpoint accepting connections on `address`, letting clients present a
+/// certificate issued by one of `client_authorities`, if any
+pub fn endpoint(
+    address: SocketAddr,
+    identity: Identity,
+    client_authorities: &[CertificateDer<'static>],
+) -> Result<Endpoint> {
+    // TLS 1.3 with the echo protocol
+    let builder = rustls::ServerConfig::builder();
+    let builder = if client_authorities.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        // a certificate is checked when presented, clients without one are served all the same
+        let mut roots = RootCertStore::empty();
+        for authority in client_authorities {
+            roots
+                .add(authority.clone())
+                .context("Invalid client certificate authority")?;
+        }
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+            .allow_unauthenticated()
+            .build()
+            .context("Invalid client certificate authorities")?;
+        builder.with_client_cert_verifier(verifier)
+    };
+    let mut crypto = builder
+        .with_single_cert(identity.certificates, identity.key)
+        .context("Invalid QUIC certificate")?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(crypto).context("Invalid QUIC TLS configuration")?;
+
+    Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), address)
+        .with_context(|| format!("Failed to listen for QUIC on {}", address))
+}
+
+/// a bidirectional stream, read and written as one
+type Stream = Join<RecvStream, SendStream>;
+
+impl Connection for Stream {
+    fn socket(&self) -> Option<&tokio::net::TcpStream> {
+        // bytes go through QUIC, never a bare socket
+        None
+    }
+}
+
+/// what a finished QUIC connection did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionSummary {
+    /// streams opened by the client
+    pub streams: u64,
+    /// streams that ended with an error
+    pub failed_streams: u64,
+    /// bytes received over all streams, after decompression
+    pub echoed_bytes: u64,
+}
+
+/// SHA-256 fingerprint of the certificate the client presented, if any
+fn client_identity(connection: &quinn::Connection) -> Option<String> {
+    let certificates = connection
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    certificates
+        .first()
+        .map(|certificate| hex::encode(Sha256::digest(certificate)))
+}
+
+/// serve the streams of one connection until the client closes it
+pub async fn handle_client(
+    connection: quinn::Connection,
+    server: Arc<Server>,
+) -> Result<ConnectionSummary, ConnectionError> {
+    // audited as a whole, streams add to the same counts
+    let session = server.audit.as_ref().map(|audit| {
+        let identity = client_identity(&connection);
+        audit.session(Transport::Quic, connection.remote_address(), None, identity)
+    });
+    let killed = async {
+        match &session {
+            Some(session) => session.killed().await,
+            None => std::future::pending().await,
+        }
+    };
+    let result = tokio::select! {
+        result = serve_streams(connection.clone(), server, session.clone()) => result,
+        // streams stop with the connection, its entry is written already
+        _ = killed => {
+            connection.close(KILLED.into(), b"killed by an administrator");
+            Err(ConnectionError::LocallyClosed)
+        }
+    };
+
+    // write why the connection ended
+    if let Some(session) = session {
+        match &result {
+            Ok(_) => session.close(CloseReason::ClientEof, None),
+            Err(e @ ConnectionError::TimedOut) => {
+                session.close(CloseReason::Timeout, Some(e.to_string()))
+            }
+            Err(e) => session.close(CloseReason::Error, Some(e.to_string())),
+        }
+    }
+
+    result
+}
+
+/// serve the streams of one connection, counting their bytes in `session`
+async fn serve_streams(
+    connection: quinn::Connection,
+    server: Arc<Server>,
+    session: Option<Arc<Session>>,
+) -> Result<ConnectionSummary, ConnectionError> {
+    // client and running streams
+    let client_address = connection.remote_address();
+    let mut streams = JoinSet::new();
+    let mut summary = ConnectionSummary::default();
+
+    // every stream the client opens
+    let closed = loop {
+        tokio::select! {
+            accepted = connection.accept_bi() => match accepted {
+                Ok((send, recv)) => {
+                    summary.streams += 1;
+                    let id = send.id();
+                    let server = server.clone();
+                    let session = session.clone();
+                    // served like a TCP connection
+                    streams.spawn(async move {
+                        let stream = tokio::io::join(recv, send);
+                        let result = match session {
+                            Some(session) => {
+                                handle_connection(session.wrap(stream), client_address, &server)
+                                    .await
+                            }
+                            None => handle_connection(stream, client_address, &server).await,
+                        };
+                        // anchor the transcript of each stream, like a TCP session
+                        if let (
+                            Some(anchor),
+                            Ok(Summary {
+                                transcript: Some(claim),
+                                ..
+                            }),
+                        ) = (&server.anchor, &result)
+                        {
+                            tokio::spawn(anchor_claim(anchor.clone(), *claim, client_address));
+                        }
+                        match &result {
+                            Ok(stream) => println!(
+                                "Stream {} from {:?} closed, with {} bytes echoed",
+                                id, client_address, stream.echoed_bytes
+                            ),
+                            Err(e) => println!("Stream {} from {:?}: {:#}", id, client_address, e),
+                        }
+                        result.map(|stream| stream.echoed_bytes)
+                    });
+                }
+                Err(e) => break e,
+            },
+            // collect finished streams as they go
+            Some(finished) = streams.join_next() => summary.add(finished),
+        }
+    };
+
+    // the rest of the streams ended with the connection
+    while let Some(finished) = streams.join_next().await {
+        summary.add(finished);
+    }
+
+    match closed {
+        // the client is done
+        ConnectionError::ApplicationClosed(_) | ConnectionError::LocallyClosed => Ok(summary),
+        e => Err(e),
+    }
+}
+
+impl ConnectionSummary {
+    /// account for a finished stream
+    fn add(&mut self, finished: Result<Result<u64>, tokio::task::JoinError>) {
+        match finished {
+            Ok(Ok(echoed_bytes)) => self.echoed_bytes += echoed_bytes,
+            _ => self.failed_streams += 1,
+        }
+    }
+}
+
+/// accept QUIC connections and process them, spawning a new task for each one
+pub async fn serve(endpoint: Endpoint, server: Arc<Server>) {
+    // until the endpoint is closed
+    while let Some(incoming) = endpoint.accept().await {
+        // share state with the new task
+        let server = server.clone();
+
+        tokio::spawn(async move {
+            // finish the handshake
+            let client_address = incoming.remote_address();
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    println!(
+                        "Failed to establish a QUIC connection from {:?}: {}",
+                        client_address, e
+                    );
+                    return;
+                }
+            };
+
+            println!("New QUIC connection from {:?}", client_address);
+            let result = handle_client(connection, server.clone()).await;
+            // account for the connection as a whole, like a TCP session
+            server.stats.record(
+                client_address.ip(),
+                result.as_ref().ok().map(|summary| summary.echoed_bytes),
+            );
+
+            match result {
+                // connection closed
+                Ok(summary) => println!(
+                    "QUIC connection from {:?} closed, with {} streams ({} failed) and {} bytes echoed",
+                    client_address, summary.streams, summary.failed_streams, summary.echoed_bytes
+                ),
+                // error happened
+                Err(e) => println!("QUIC connection from {:?} failed: {}", client_address, e),
+            }
+        });
+    }
+}
@@ -0,0 +1,268 @@
+// how the server is spread over threads
+//
+//   multi-thread     one tokio runtime with a pool of worker threads (default)
+//   current-thread   everything on the main thread
+//   thread-per-core  one single-threaded runtime per core, each pinned to its core
+//                    and accepting on its own SO_REUSEPORT listener
+//   io-uring         like thread-per-core, echoing through one io_uring per thread
+//                    (Linux, with the io-uring cargo feature)
+
+// use tokio for async runtime
+use tokio::net::TcpListener;
+use tokio::runtime::{Builder, Runtime};
+
+// use socket2 to check how listeners were set up
+use socket2::SockRef;
+
+// use anyhow for error handling
+use anyhow::{bail, Context, Result};
+
+// use clap to read options from command line
+use clap::{Args, ValueEnum};
+
+// types for I/O results and sharing
+use std::io;
+use std::sync::Arc;
+
+// accepting and echoing connections
+use crate::server::{serve, Server};
+
+/// layout of runtimes and threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Flavor {
+    /// one runtime with a pool of worker threads
+    MultiThread,
+    /// one runtime on the main thread only
+    CurrentThread,
+    /// one single-threaded runtime per core, pinned to it
+    ThreadPerCore,
+    /// like thread-per-core, echoing through io_uring (Linux, io-uring feature)
+    IoUring,
+}
+
+impl Flavor {
+    /// whether connections are served by threads of their own rather than the main runtime
+    pub fn per_core(self) -> bool {
+        matches!(self, Flavor::ThreadPerCore | Flavor::IoUring)
+    }
+}
+
+/// options of the runtime
+#[derive(Debug, Clone, Args)]
+pub struct RuntimeOptions {
+    /// how connections are spread over threads
+    #[arg(long, value_enum, default_value_t = Flavor::MultiThread)]
+    pub runtime: Flavor,
+
+    /// worker threads of the multi-thread runtime, or threads of thread-per-core;
+    /// one per available core by default
+    #[arg(long, value_name = "COUNT")]
+    pub worker_threads: Option<usize>,
+}
+
+impl RuntimeOptions {
+    /// runtime of the main thread; with thread-per-core it only handles signals and housekeeping
+    pub fn build(&self) -> Result<Runtime> {
+        // zero threads would serve nothing
+        if self.worker_threads == Some(0) {
+            bail!("--worker-threads must be at least 1");
+        }
+
+        let runtime = match self.runtime {
+            Flavor::MultiThread => {
+                // tokio picks one worker per core unless told otherwise
+                let mut builder = Builder::new_multi_thread();
+                if let Some(threads) = self.worker_threads {
+                    builder.worker_threads(threads);
+                }
+                builder.enable_all().build()
+            }
+            Flavor::CurrentThread => {
+                // there are no workers to count
+                if self.worker_threads.is_some() {
+                    bail!("--worker-threads cannot be combined with --runtime current-thread");
+                }
+                Builder::new_current_thread().enable_all().build()
+            }
+            Flavor::ThreadPerCore => Builder::new_current_thread().enable_all().build(),
+            Flavor::IoUring => {
+                // the backend is only compiled in on request
+                if !cfg!(all(target_os = "linux", feature = "io-uring")) {
+                    bail!("--runtime io-uring needs a Linux build with the io-uring feature");
+                }
+                Builder::new_current_thread().enable_all().build()
+            }
+        };
+
+        runtime.context("Failed to start runtime")
+    }
+
+    /// cores the threads of thread-per-core are pinned to, one entry per thread
+    pub fn cores(&self) -> Vec<usize> {
+        // cores this process may run on
+        let available = available_cores();
+        // one thread per core unless told otherwise, wrapping around when there are more
+        let threads = self.worker_threads.unwrap_or(available.len());
+
+        available.iter().copied().cycle().take(threads).collect()
+    }
+}
+
+/// cores this process is allowed to run on
+#[cfg(target_os = "linux")]
+pub fn available_cores() -> Vec<usize> {
+    // ask the scheduler for our affinity mask
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0
+    {
+        return vec![0];
+    }
+
+    // every core set in the mask
+    (0..libc::CPU_SETSIZE as usize)
+        .filter(|&core| unsafe { libc::CPU_ISSET(core, &set) })
+        .collect()
+}
+
+/// cores this process is allowed to run on
+#[cfg(not(target_os = "linux"))]
+pub fn available_cores() -> Vec<usize> {
+    // no affinity masks, count the cores
+    let count = std::thread::available_parallelism().map_or(1, |count| count.get());
+
+    (0..count).collect()
+}
+
+/// keep the calling thread on `core`
+#[cfg(target_os = "linux")]
+pub fn pin_to_core(core: usize) -> io::Result<()> {
+    // mask with only this core
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(core, &mut set) };
+
+    // 0 is the calling thread
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// keep the calling thread on `core`
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_core(_core: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads to cores is only supported on Linux",
+    ))
+}
+
+/// listeners for one more thread: new SO_REUSEPORT sockets on the same addresses,
+/// or the same sockets shared when they cannot be bound twice (e.g. passed by systemd)
+fn listeners_for_thread(
+    server: &Server,
+    listeners: &[std::net::TcpListener],
+) -> Result<Vec<std::net::TcpListener>> {
+    listeners
+        .iter()
+        .map(|listener| {
+            // the kernel balances connections between sockets sharing a port
+            if SockRef::from(listener).reuse_port()? {
+                server.config.socket.bind(listener.local_addr()?)
+            } else {
+                Ok(listener.try_clone()?)
+            }
+        })
+        .collect()
+}
+
+/// serve `listeners` on the calling thread with a runtime of its own, until the process exits
+fn run_thread(server: Arc<Server>, listeners: Vec<std::net::TcpListener>, core: usize) {
+    // echo through the ring when asked to
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if server.config.runtime.runtime == Flavor::IoUring {
+        return crate::uring::run_thread(server, listeners, core);
+    }
+
+    // a runtime of its own, no work stealing between cores
+    let runtime = match Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            println!("Failed to start runtime on core {}: {}", core, e);
+            return;
+        }
+    };
+
+    runtime.block_on(async move {
+        for listener in listeners {
+            // hand the socket over to tokio
+            match TcpListener::from_std(listener) {
+                Ok(listener) => {
+                    if let Ok(address) = listener.local_addr() {
+                        println!("Server listening on {} (core {})", address, core);
+                    }
+                    // serve it on this thread
+                    tokio::spawn(serve(listener, server.clone()));
+                }
+                Err(e) => println!("Failed to initialize TCP server: {}", e),
+            }
+        }
+        // run until the process exits
+        std::future::pending::<()>().await
+    });
+}
+
+/// listeners for each entry of `cores`: the first thread takes `listeners`, the others get their own
+pub fn listeners_per_core(
+    server: &Server,
+    listeners: Vec<std::net::TcpListener>,
+    cores: &[usize],
+) -> Result<Vec<Vec<std::net::TcpListener>>> {
+    // nothing would accept connections
+    if cores.is_empty() {
+        bail!("No cores to serve on");
+    }
+
+    // bound up front, so this can happen before privileges are dropped
+    let mut per_core = Vec::with_capacity(cores.len());
+    for _ in 1..cores.len() {
+        per_core.push(listeners_for_thread(server, &listeners)?);
+    }
+    per_core.insert(0, listeners);
+
+    Ok(per_core)
+}
+
+/// serve each entry of `listeners` with a single-threaded runtime pinned to the matching core
+pub fn spawn_per_core(
+    server: Arc<Server>,
+    listeners: Vec<Vec<std::net::TcpListener>>,
+    cores: &[usize],
+) -> Result<()> {
+    for (thread, (listeners, &core)) in listeners.into_iter().zip(cores).enumerate() {
+        let server = server.clone();
+
+        std::thread::Builder::new()
+            .name(format!("worker-{}", thread))
+            .spawn(move || {
+                // stay on this core
+                if let Err(e) = pin_to_core(core) {
+                    println!("Failed to pin thread {} to core {}: {}", thread, core, e);
+                }
+                run_thread(server, listeners, core);
+            })
+            .context("Failed to start thread")?;
+    }
+
+    Ok(())
+}
+
+/// serve `listeners` with one single-threaded runtime per entry of `cores`, each pinned to its core
+pub fn serve_per_core(
+    server: Arc<Server>,
+    listeners: Vec<std::net::TcpListener>,
+    cores: &[usize],
+) -> Result<()> {
+    let listeners = listeners_per_core(&server, listeners, cores)?;
+    spawn_per_core(server, listeners, cores)
+}
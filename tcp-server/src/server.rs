@@ -0,0 +1,622 @@
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// use async-compression for compressed sessions
+use async_compression::tokio::bufread::{DeflateDecoder, ZstdDecoder};
+use async_compression::tokio::write::{DeflateEncoder, ZstdEncoder};
+
+// use anyhow for error handling
+use anyhow::{anyhow, bail, Context, Result};
+
+// use clap to read options from command line
+use clap::ValueEnum;
+
+// types for I/O results, socket address, counters, sharing and timeouts
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// connection audit log
+use crate::audit::{AuditLog, Audited, Transport};
+// authentication handshake
+use crate::auth::Auth;
+// traffic capture
+use crate::capture::{Capture, Captured};
+// self-checking echo
+use crate::checksum;
+// line protocol
+use crate::command;
+// stream compression
+use crate::compression::{self, Algorithm, Counted, KeepOpen, Ratio};
+// command line options
+use crate::config::Config;
+// ways of copying bytes back
+use crate::echo::{self, CopyMode};
+// HTTP echo
+use crate::http;
+// multiplexed virtual streams
+use crate::mux;
+// WebAssembly transform plugins
+use crate::plugin::{self, Plugin};
+// proof-of-existence anchoring
+use crate::poe::{CallIndex, NodeSubmitter, Submitter};
+// layout of runtimes and threads
+use crate::runtime::Flavor;
+// SOCKS5 proxy
+use crate::socks;
+// statistics per client
+use crate::stats::Stats;
+// session transcript hashing
+use crate::transcript::Recorded;
+
+/// protocol spoken with clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Protocol {
+    /// echo raw bytes
+    Tcp,
+    /// answer HTTP/1.1 requests with a description of the request
+    Http,
+    /// echo lines, accepting control commands such as `MODE upper`
+    Line,
+    /// echo each virtual stream of a multiplexed connection, with flow control
+    Mux,
+    /// act as a SOCKS5 proxy, answering the echo target itself
+    Socks5,
+    /// answer length-prefixed frames with a checksum of each
+    Checksum,
+}
+
+/// state shared by all connections
+pub struct Server {
+    pub config: Config,
+    /// set when clients must authenticate
+    pub auth: Option<Auth>,
+    /// statistics per client
+    pub stats: Arc<Stats>,
+    /// set when session transcripts are anchored as PoE claims
+    pub anchor: Option<Arc<dyn Submitter>>,
+    /// set when traffic is written to a capture file
+    pub capture: Option<Arc<Capture>>,
+    /// set when a WebAssembly plugin answers clients
+    pub plugin: Option<Plugin>,
+    /// set when connections are written to an audit log
+    pub audit: Option<Arc<AuditLog>>,
+}
+
+impl Server {
+    /// set up shared state from options
+    pub fn new(config: Config) -> Result<Self> {
+        // splice moves bytes without looking at them
+        if config.compression && config.copy_mode == CopyMode::Splice {
+            bail!("--copy-mode splice cannot be combined with --compression");
+        }
+
+        // SOCKS clients speak first, and splice would skip the handshake
+        if config.protocol == Protocol::Socks5
+            && (config.compression || config.copy_mode == CopyMode::Splice)
+        {
+            bail!("--protocol socks5 cannot be combined with --compression or --copy-mode splice");
+        }
+
+        // virtual streams could never carry data
+        if config.mux_window == 0 {
+            bail!("--mux-window must be at least 1");
+        }
+
+        // copies could never make progress
+        if config.buffer_size == 0 {
+            bail!("--buffer-size must be at least 1");
+        }
+
+        // keys given on command line or in a file
+        let mut keys: Vec<Vec<u8>> = config
+            .auth_key
+            .iter()
+            .map(|key| key.as_bytes().to_vec())
+            .collect();
+        if let Some(path) = &config.auth_tokens {
+            keys.extend(Auth::read_tokens(path)?);
+        }
+        // authentication is on as soon as some key is configured
+        let auth = if config.auth_key.is_some() || config.auth_tokens.is_some() {
+            Some(Auth::new(
+                keys,
+                Duration::from_millis(config.auth_timeout),
+                Duration::from_millis(config.auth_failure_window),
+            ))
+        } else {
+            None
+        };
+
+        // claims are signed with the given key
+        let anchor = match &config.poe_node {
+            Some(url) => {
+                // splice moves bytes without looking at them
+                if config.copy_mode == CopyMode::Splice {
+                    bail!("--copy-mode splice cannot be combined with --poe-node");
+                }
+                let seed = hex::decode(&config.poe_seed)
+                    .map_err(|e| anyhow!("Invalid --poe-seed: {}", e))?;
+                let call_index = CallIndex {
+                    pallet: config.poe_pallet_index,
+                    call: config.poe_call_index,
+                };
+                let timeout = Duration::from_millis(config.poe_timeout);
+                let submitter: Arc<dyn Submitter> =
+                    Arc::new(NodeSubmitter::new(url, &seed, call_index, timeout)?);
+                Some(submitter)
+            }
+            None => None,
+        };
+
+        // QUIC streams are not sockets
+        if config.quic.is_some() && config.copy_mode == CopyMode::Splice {
+            bail!("--copy-mode splice cannot be combined with --quic");
+        }
+
+        // one capture file for all connections
+        let capture = match &config.capture {
+            Some(path) => {
+                // splice moves bytes without looking at them
+                if config.copy_mode == CopyMode::Splice {
+                    bail!("--copy-mode splice cannot be combined with --capture");
+                }
+                Some(Arc::new(Capture::create(
+                    path,
+                    config.capture_format,
+                    config.capture_max_size,
+                    config.capture_files,
+                )?))
+            }
+            None => None,
+        };
+
+        // one audit log for all connections, appended to
+        let audit = match &config.audit_log {
+            Some(path) => {
+                // splice moves bytes without counting them
+                if config.copy_mode == CopyMode::Splice {
+                    bail!("--copy-mode splice cannot be combined with --audit-log");
+                }
+                Some(Arc::new(AuditLog::open(
+                    path,
+                    config.audit_max_size,
+                    config.audit_files,
+                )?))
+            }
+            None => None,
+        };
+
+        // compiled once, instantiated per connection
+        let plugin = match &config.wasm_plugin {
+            Some(path) => {
+                // the plugin answers raw chunks, which splice never sees
+                if config.protocol != Protocol::Tcp || config.copy_mode == CopyMode::Splice {
+                    bail!("--wasm-plugin only works with --protocol tcp and a copy mode other than splice");
+                }
+                Some(Plugin::load(
+                    path,
+                    config.wasm_memory_limit,
+                    config.wasm_fuel,
+                )?)
+            }
+            None => None,
+        };
+
+        // the ring only echoes raw bytes
+        if config.runtime.runtime == Flavor::IoUring
+            && (config.protocol != Protocol::Tcp
+                || config.copy_mode == CopyMode::Splice
+                || config.compression
+                || auth.is_some()
+                || anchor.is_some()
+                || capture.is_some()
+                || plugin.is_some()
+                || audit.is_some())
+        {
+            bail!(
+                "--runtime io-uring only echoes raw bytes, without another --protocol, \
+                 splice, compression, authentication, anchoring, capture, plugins or audit log"
+            );
+        }
+
+        // pick up where the last run stopped
+        let stats = match &config.stats_file {
+            Some(path) => Stats::load(path)?,
+            None => Stats::default(),
+        };
+
+        Ok(Server {
+            config,
+            auth,
+            stats: Arc::new(stats),
+            anchor,
+            capture,
+            plugin,
+            audit,
+        })
+    }
+}
+
+/// what a finished session did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    /// bytes received from the client, after decompression
+    pub echoed_bytes: u64,
+    /// sizes on the wire, for compressed sessions
+    pub compression: Option<Ratio>,
+    /// BLAKE2 hash of the transcript, for anchored sessions
+    pub transcript: Option<[u8; 32]>,
+}
+
+/// speak the configured protocol over any stream until the client is done
+async fn speak<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<u64> {
+    // options
+    let config = &server.config;
+
+    // the plugin answers instead of the echo
+    if let Some(plugin) = &server.plugin {
+        return plugin::serve_connection(
+            stream,
+            client_address,
+            plugin,
+            config.buffer_size,
+            config.quiet,
+        )
+        .await;
+    }
+
+    match config.protocol {
+        Protocol::Tcp => match config.copy_mode {
+            CopyMode::Loop => echo::echo_loop(stream, client_address, config.quiet).await,
+            CopyMode::Buffered => echo::echo_buffered(stream, config.buffer_size).await,
+            // splice needs the bare socket
+            CopyMode::Splice => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "splice only works on plain TCP connections",
+            )),
+        },
+        Protocol::Http => {
+            http::serve_connection(stream, client_address, config.http_format, config.quiet).await
+        }
+        Protocol::Line => command::serve_connection(stream, client_address, config.quiet).await,
+        Protocol::Mux => {
+            mux::serve_connection(
+                stream,
+                client_address,
+                config.mux_window,
+                config.mux_max_streams,
+                config.quiet,
+            )
+            .await
+        }
+        Protocol::Socks5 => socks::serve_connection(stream, client_address, server).await,
+        Protocol::Checksum => {
+            checksum::serve_connection(
+                stream,
+                client_address,
+                config.checksum,
+                config.checksum_mode,
+                config.quiet,
+            )
+            .await
+        }
+    }
+}
+
+/// connections accepted from clients
+pub(crate) trait Connection: AsyncRead + AsyncWrite + Unpin {
+    /// the bare socket, when bytes may bypass user space
+    fn socket(&self) -> Option<&TcpStream>;
+}
+
+impl Connection for TcpStream {
+    fn socket(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+impl<C: Connection> Connection for Captured<C> {
+    fn socket(&self) -> Option<&TcpStream> {
+        // captured bytes must go through user space
+        None
+    }
+}
+
+impl<C: Connection> Connection for Audited<C> {
+    fn socket(&self) -> Option<&TcpStream> {
+        // counted bytes must go through user space
+        None
+    }
+}
+
+/// speak the configured protocol, on the bare socket when possible
+async fn speak_plain<C: Connection>(
+    connection: &mut C,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<u64> {
+    // options
+    let config = &server.config;
+
+    match (config.protocol, config.copy_mode, connection.socket()) {
+        #[cfg(target_os = "linux")]
+        (Protocol::Tcp, CopyMode::Splice, Some(socket)) => {
+            crate::splice::echo(socket, config.buffer_size).await
+        }
+        _ => speak(connection, client_address, server).await,
+    }
+}
+
+/// speak the configured protocol through the compression the client asks for
+async fn speak_compressed<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<(u64, Ratio)> {
+    // algorithm from header
+    let algorithm = compression::negotiate(stream).await?;
+    // split stream, counting what arrives on the wire
+    let (reader, writer) = tokio::io::split(stream);
+    let compressed = Arc::new(AtomicU64::new(0));
+    let reader = BufReader::new(Counted::new(reader, compressed.clone()));
+    // finishing the compressed stream must not close the connection yet
+    let writer = KeepOpen(writer);
+
+    // wrap both directions
+    let uncompressed = match algorithm {
+        Algorithm::None => {
+            speak(&mut tokio::io::join(reader, writer), client_address, server).await?
+        }
+        Algorithm::Deflate => {
+            let mut stream =
+                tokio::io::join(DeflateDecoder::new(reader), DeflateEncoder::new(writer));
+            let n = speak(&mut stream, client_address, server).await?;
+            // write the end of the compressed stream
+            stream.shutdown().await?;
+            n
+        }
+        Algorithm::Zstd => {
+            let mut stream = tokio::io::join(ZstdDecoder::new(reader), ZstdEncoder::new(writer));
+            let n = speak(&mut stream, client_address, server).await?;
+            // write the end of the compressed stream
+            stream.shutdown().await?;
+            n
+        }
+    };
+
+    Ok((
+        uncompressed,
+        Ratio {
+            compressed: compressed.load(Ordering::Relaxed),
+            uncompressed,
+        },
+    ))
+}
+
+/// speak the configured protocol, compressed when asked to, over any stream
+async fn converse<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<Summary> {
+    if server.config.compression {
+        speak_compressed(stream, client_address, server)
+            .await
+            .map(|(echoed_bytes, ratio)| Summary {
+                echoed_bytes,
+                compression: Some(ratio),
+                transcript: None,
+            })
+    } else {
+        speak(stream, client_address, server)
+            .await
+            .map(|echoed_bytes| Summary {
+                echoed_bytes,
+                compression: None,
+                transcript: None,
+            })
+    }
+}
+
+/// handle a TCP stream from client
+pub async fn handle_client(
+    socket: TcpStream,
+    client_address: SocketAddr,
+    server: &Server,
+) -> Result<Summary> {
+    // the other end of the connection
+    let local_address = socket.local_addr().context("Failed to get local address")?;
+
+    match &server.audit {
+        // count what goes over the wire and write why the connection ended
+        Some(audit) => {
+            let session = audit.session(Transport::Tcp, client_address, Some(local_address), None);
+            let result = tokio::select! {
+                result = handle_captured(session.wrap(socket), client_address, local_address, server) => result,
+                // dropping the connection closes it
+                _ = session.killed() => Err(anyhow!("Connection from {:?} killed by an administrator", client_address)),
+            };
+            session.close_with(&result);
+            result
+        }
+        None => handle_captured(socket, client_address, local_address, server).await,
+    }
+}
+
+/// write the connection to the capture file, if any, and serve it
+async fn handle_captured<C: Connection>(
+    socket: C,
+    client_address: SocketAddr,
+    local_address: SocketAddr,
+    server: &Server,
+) -> Result<Summary> {
+    match &server.capture {
+        // write everything exchanged to the capture file
+        Some(capture) => {
+            let captured = capture.connection(socket, client_address, local_address);
+            handle_connection(captured, client_address, server).await
+        }
+        None => handle_connection(socket, client_address, server).await,
+    }
+}
+
+/// authenticate and serve a connection until both sides are done
+pub(crate) async fn handle_connection<C: Connection>(
+    mut socket: C,
+    client_address: SocketAddr,
+    server: &Server,
+) -> Result<Summary> {
+    // options
+    let config = &server.config;
+
+    // only authenticated clients get echoed, SOCKS clients authenticate in their own handshake
+    if let Some(auth) = server
+        .auth
+        .as_ref()
+        .filter(|_| config.protocol != Protocol::Socks5)
+    {
+        auth.handshake(&mut socket, client_address.ip()).await?;
+    }
+
+    // speak the configured protocol until the client is done
+    let summary = if server.anchor.is_some() {
+        // hash everything exchanged from now on
+        let mut recorded = Recorded::new(&mut socket);
+        let summary = converse(&mut recorded, client_address, server).await;
+        summary.map(|summary| Summary {
+            transcript: Some(recorded.finish()),
+            ..summary
+        })
+    } else if config.compression {
+        converse(&mut socket, client_address, server).await
+    } else {
+        speak_plain(&mut socket, client_address, server)
+            .await
+            .map(|echoed_bytes| Summary {
+                echoed_bytes,
+                compression: None,
+                transcript: None,
+            })
+    }
+    .with_context(|| format!("Failed to echo from {:?}", client_address))?;
+
+    // everything is echoed, give the client more time when asked to
+    if let Some(linger) = config.half_close_linger {
+        tokio::time::sleep(Duration::from_millis(linger)).await;
+    }
+    // tell the client we are done as well
+    socket
+        .shutdown()
+        .await
+        .context("Failed to shut down connection")?;
+
+    Ok(summary)
+}
+
+/// submit the transcript hash of a session
+pub(crate) async fn anchor_claim(
+    anchor: Arc<dyn Submitter>,
+    claim: [u8; 32],
+    client_address: SocketAddr,
+) {
+    match anchor.submit(claim).await {
+        // in the pool of the node
+        Ok(extrinsic) => println!(
+            "Transcript of {:?} anchored as 0x{} in extrinsic {}",
+            client_address,
+            hex::encode(claim),
+            extrinsic
+        ),
+        // the session is over anyway
+        Err(e) => println!(
+            "Failed to anchor transcript 0x{} of {:?}: {:#}",
+            hex::encode(claim),
+            client_address,
+            e
+        ),
+    }
+}
+
+/// accept connections on a listener and process them, spawning a new task for each one
+pub async fn serve(listener: TcpListener, server: Arc<Server>) {
+    loop {
+        // try to accept an incoming connection
+        match listener.accept().await {
+            // when connection established
+            Ok((socket, client_address)) => {
+                // tune the accepted connection
+                if let Err(e) = server.config.socket.apply_stream(&socket) {
+                    println!(
+                        "Failed to configure connection from {:?}: {:#}",
+                        client_address, e
+                    );
+                }
+
+                // share state with the new task
+                let server = server.clone();
+                // spawn a new task to handle this connection
+                tokio::spawn(async move {
+                    println!("New connection from {:?}", client_address);
+                    let result = handle_client(socket, client_address, &server).await;
+                    // account for the session
+                    server.stats.record(
+                        client_address.ip(),
+                        result.as_ref().ok().map(|s| s.echoed_bytes),
+                    );
+                    // anchor the transcript without holding up the session
+                    if let (
+                        Some(anchor),
+                        Ok(Summary {
+                            transcript: Some(claim),
+                            ..
+                        }),
+                    ) = (&server.anchor, &result)
+                    {
+                        tokio::spawn(anchor_claim(anchor.clone(), *claim, client_address));
+                    }
+
+                    match result {
+                        // connection closed
+                        Ok(Summary {
+                            echoed_bytes,
+                            compression: None,
+                            ..
+                        }) => {
+                            println!(
+                                "Connection from {:?} closed, with {} bytes echoed",
+                                client_address, echoed_bytes
+                            );
+                        }
+                        // connection closed, with compression
+                        Ok(Summary {
+                            echoed_bytes,
+                            compression: Some(ratio),
+                            ..
+                        }) => {
+                            println!(
+                                "Connection from {:?} closed, with {} bytes echoed ({} compressed, ratio {:.2})",
+                                client_address, echoed_bytes, ratio.compressed, ratio.ratio()
+                            );
+                        }
+                        // error happened
+                        Err(e) => {
+                            println!("{:#}", e);
+                        }
+                    }
+                });
+            }
+            // when connection failed to be established
+            Err(e) => {
+                println!("Failed to establish a connection: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// use socket2 to reach socket options tokio does not expose
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+// use anyhow for error handling
+use anyhow::{Context, Result};
+
+// use clap to read options from command line
+use clap::Args;
+
+// types for socket address and timeouts
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// options applied to the listening socket and every accepted connection
+#[derive(Debug, Clone, Args)]
+pub struct SocketOptions {
+    /// disable Nagle's algorithm (TCP_NODELAY)
+    #[arg(long)]
+    pub nodelay: bool,
+
+    /// enable TCP keepalive probes (SO_KEEPALIVE)
+    #[arg(long)]
+    pub keepalive: bool,
+
+    /// seconds of idle time before the first keepalive probe
+    #[arg(long, value_name = "SECS", requires = "keepalive")]
+    pub keepalive_idle: Option<u64>,
+
+    /// seconds between two keepalive probes
+    #[arg(long, value_name = "SECS", requires = "keepalive")]
+    pub keepalive_interval: Option<u64>,
+
+    /// number of unanswered probes before the connection is dropped
+    #[arg(long, value_name = "COUNT", requires = "keepalive")]
+    pub keepalive_count: Option<u32>,
+
+    /// allow several processes to bind the same address (SO_REUSEPORT)
+    #[arg(long)]
+    pub reuse_port: bool,
+
+    /// size of the kernel receive buffer (SO_RCVBUF)
+    #[arg(long, value_name = "BYTES")]
+    pub recv_buffer_size: Option<usize>,
+
+    /// size of the kernel send buffer (SO_SNDBUF)
+    #[arg(long, value_name = "BYTES")]
+    pub send_buffer_size: Option<usize>,
+
+    /// maximum length of the queue of pending connections
+    #[arg(long, default_value_t = 1024)]
+    pub backlog: i32,
+
+    /// accept IPv6 connections only on an IPv6 address (IPV6_V6ONLY)
+    #[arg(long)]
+    pub only_v6: bool,
+}
+
+impl SocketOptions {
+    /// create a listening socket bound to `address` with these options
+    pub fn bind(&self, address: SocketAddr) -> Result<std::net::TcpListener> {
+        // create a bare TCP socket of the right family
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )
+        .context("Failed to create socket")?;
+
+        // allow quick restarts, as tokio does for its own listeners
+        socket
+            .set_reuse_address(true)
+            .context("Failed to set SO_REUSEADDR")?;
+        // share the port with other processes when asked
+        if self.reuse_port {
+            socket
+                .set_reuse_port(true)
+                .context("Failed to set SO_REUSEPORT")?;
+        }
+        // only meaningful on IPv6 sockets
+        if address.is_ipv6() {
+            socket
+                .set_only_v6(self.only_v6)
+                .context("Failed to set IPV6_V6ONLY")?;
+        }
+        // options inherited by accepted connections
+        self.apply(&socket)?;
+
+        // bind to address
+        socket
+            .bind(&address.into())
+            .with_context(|| format!("Failed to bind {}", address))?;
+        // start listening
+        socket
+            .listen(self.backlog)
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        // tokio requires non-blocking sockets
+        socket
+            .set_nonblocking(true)
+            .context("Failed to set non-blocking mode")?;
+
+        Ok(socket.into())
+    }
+
+    /// apply per-connection options to an accepted stream
+    pub fn apply_stream(&self, stream: &tokio::net::TcpStream) -> Result<()> {
+        self.apply(&SockRef::from(stream))
+    }
+
+    /// apply the options shared by listening and connected sockets
+    fn apply(&self, socket: &Socket) -> Result<()> {
+        // disable Nagle's algorithm
+        if self.nodelay {
+            socket
+                .set_tcp_nodelay(true)
+                .context("Failed to set TCP_NODELAY")?;
+        }
+        // enable keepalive probes with the requested timings
+        if self.keepalive {
+            socket
+                .set_tcp_keepalive(&self.keepalive_params())
+                .context("Failed to set SO_KEEPALIVE")?;
+        }
+        // resize kernel buffers
+        if let Some(size) = self.recv_buffer_size {
+            socket
+                .set_recv_buffer_size(size)
+                .context("Failed to set SO_RCVBUF")?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket
+                .set_send_buffer_size(size)
+                .context("Failed to set SO_SNDBUF")?;
+        }
+
+        Ok(())
+    }
+
+    /// keepalive timings, left to the kernel defaults when not given
+    fn keepalive_params(&self) -> TcpKeepalive {
+        // start with plain SO_KEEPALIVE
+        let mut params = TcpKeepalive::new();
+
+        // override each timing when configured
+        if let Some(idle) = self.keepalive_idle {
+            params = params.with_time(Duration::from_secs(idle));
+        }
+        if let Some(interval) = self.keepalive_interval {
+            params = params.with_interval(Duration::from_secs(interval));
+        }
+        if let Some(count) = self.keepalive_count {
+            params = params.with_retries(count);
+        }
+
+        params
+    }
+}
@@ -0,0 +1,358 @@
+// SOCKS5 proxy (RFC 1928) with username/password authentication (RFC 1929), CONNECT only
+//
+// connections to --socks-echo-target are answered by the echo itself, so harnesses can run
+// without any upstream; other targets are only relayed with --socks-upstream
+//
+// without keys, clients must offer "no authentication"; with --auth-key or --auth-tokens they
+// must offer username/password, with one of the keys as password and any username
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// standard library types
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+// ways of copying bytes back
+use crate::echo::{self, CopyMode};
+// accepting and echoing connections
+use crate::server::Server;
+
+/// protocol version
+const VERSION: u8 = 5;
+
+/// authentication methods
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+
+/// version of the username/password subnegotiation
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+
+/// the only supported command
+const CONNECT: u8 = 1;
+
+/// address types
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+
+/// reply codes
+const SUCCEEDED: u8 = 0;
+const GENERAL_FAILURE: u8 = 1;
+const NOT_ALLOWED: u8 = 2;
+const NETWORK_UNREACHABLE: u8 = 3;
+const HOST_UNREACHABLE: u8 = 4;
+const CONNECTION_REFUSED: u8 = 5;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// destination of a CONNECT request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// IPv4 or IPv6 address
+    Address(SocketAddr),
+    /// host name, resolved by the proxy
+    Domain(String, u16),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    /// `HOST:PORT`, `IPV4:PORT` or `[IPV6]:PORT`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // addresses first, so IPv6 colons are not taken for the port
+        if let Ok(address) = s.parse() {
+            return Ok(Target::Address(address));
+        }
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("{} is not HOST:PORT", s))?;
+        let port = port
+            .parse()
+            .map_err(|e| format!("invalid port in {}: {}", s, e))?;
+        if host.is_empty() || host.len() > 255 {
+            return Err(format!("invalid host in {}", s));
+        }
+
+        Ok(Target::Domain(host.to_string(), port))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Address(address) => write!(f, "{}", address),
+            Target::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl Target {
+    /// whether a request for `self` means `other`, ignoring the case of host names
+    fn matches(&self, other: &Target) -> bool {
+        match (self, other) {
+            (Target::Domain(host, port), Target::Domain(other_host, other_port)) => {
+                port == other_port && host.eq_ignore_ascii_case(other_host)
+            }
+            _ => self == other,
+        }
+    }
+
+    /// append the address in SOCKS form: type, address, port
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Target::Address(SocketAddr::V4(address)) => {
+                out.push(IPV4);
+                out.extend_from_slice(&address.ip().octets());
+            }
+            Target::Address(SocketAddr::V6(address)) => {
+                out.push(IPV6);
+                out.extend_from_slice(&address.ip().octets());
+            }
+            Target::Domain(host, _) => {
+                out.push(DOMAIN);
+                out.push(host.len() as u8);
+                out.extend_from_slice(host.as_bytes());
+            }
+        }
+        let port = match self {
+            Target::Address(address) => address.port(),
+            Target::Domain(_, port) => *port,
+        };
+        out.extend_from_slice(&port.to_be_bytes());
+    }
+}
+
+/// a protocol violation
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// read a byte string prefixed by its length
+async fn read_string<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let length = stream.read_u8().await?;
+    let mut string = vec![0u8; length as usize];
+    stream.read_exact(&mut string).await?;
+
+    Ok(string)
+}
+
+/// read the destination of a request
+async fn read_target<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<Target>> {
+    let target = match stream.read_u8().await? {
+        IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            let port = stream.read_u16().await?;
+            Target::Address(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+        }
+        IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            let port = stream.read_u16().await?;
+            Target::Address(SocketAddr::new(Ipv6Addr::from(ip).into(), port))
+        }
+        DOMAIN => {
+            let host = read_string(stream).await?;
+            let port = stream.read_u16().await?;
+            let host = String::from_utf8(host).map_err(|_| invalid("host name is not UTF-8"))?;
+            Target::Domain(host, port)
+        }
+        // the rest of the request cannot be parsed
+        _ => return Ok(None),
+    };
+
+    Ok(Some(target))
+}
+
+/// send a reply to the request
+async fn reply<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    code: u8,
+    bound: SocketAddr,
+) -> io::Result<()> {
+    let mut message = vec![VERSION, code, 0];
+    Target::Address(bound).encode(&mut message);
+    stream.write_all(&message).await?;
+    stream.flush().await
+}
+
+/// pick a method and authenticate the client, failing when it is not allowed in
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<()> {
+    // greeting with the methods the client offers
+    if stream.read_u8().await? != VERSION {
+        return Err(invalid("not a SOCKS5 greeting"));
+    }
+    let methods = read_string(stream).await?;
+
+    // keys configured means passwords are required
+    let method = match &server.auth {
+        Some(_) => USERNAME_PASSWORD,
+        None => NO_AUTHENTICATION,
+    };
+    if !methods.contains(&method) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        return Err(invalid("no acceptable authentication method offered"));
+    }
+    stream.write_all(&[VERSION, method]).await?;
+
+    let auth = match &server.auth {
+        Some(auth) => auth,
+        None => return Ok(()),
+    };
+
+    // username and password
+    if stream.read_u8().await? != USERNAME_PASSWORD_VERSION {
+        return Err(invalid("not a username/password request"));
+    }
+    let username = read_string(stream).await?;
+    let password = read_string(stream).await?;
+
+    if auth.accepts(&password) {
+        stream.write_all(&[USERNAME_PASSWORD_VERSION, 0]).await?;
+        Ok(())
+    } else {
+        // the client may already be gone
+        let _ = stream.write_all(&[USERNAME_PASSWORD_VERSION, 1]).await;
+        let failures = auth.record_failure(client_address.ip());
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "SOCKS authentication failed for {} as {:?} ({} failed attempts)",
+                client_address.ip(),
+                String::from_utf8_lossy(&username),
+                failures
+            ),
+        ))
+    }
+}
+
+/// the greeting, authentication and request, returning the target of a CONNECT
+async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<Target> {
+    authenticate(stream, client_address, server).await?;
+
+    // request header
+    let mut header = [0u8; 3];
+    stream.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        return Err(invalid("not a SOCKS5 request"));
+    }
+    let target = match read_target(stream).await? {
+        Some(target) => target,
+        None => {
+            reply(stream, ADDRESS_TYPE_NOT_SUPPORTED, unspecified()).await?;
+            return Err(invalid("unsupported address type"));
+        }
+    };
+
+    // no BIND or UDP ASSOCIATE
+    if header[1] != CONNECT {
+        reply(stream, COMMAND_NOT_SUPPORTED, unspecified()).await?;
+        return Err(invalid("only CONNECT is supported"));
+    }
+
+    Ok(target)
+}
+
+/// 0.0.0.0:0, for replies without a meaningful address
+fn unspecified() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+}
+
+/// reply code for a failed connection to an upstream
+fn failure_code(e: &io::Error) -> u8 {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => CONNECTION_REFUSED,
+        io::ErrorKind::TimedOut => HOST_UNREACHABLE,
+        _ if e.raw_os_error() == Some(libc::ENETUNREACH) => NETWORK_UNREACHABLE,
+        _ if e.raw_os_error() == Some(libc::EHOSTUNREACH) => HOST_UNREACHABLE,
+        _ => GENERAL_FAILURE,
+    }
+}
+
+/// connect to an upstream target within `timeout`
+async fn connect(target: &Target, timeout: Duration) -> io::Result<TcpStream> {
+    let connecting = async {
+        match target {
+            Target::Address(address) => TcpStream::connect(address).await,
+            Target::Domain(host, port) => TcpStream::connect((host.as_str(), *port)).await,
+        }
+    };
+
+    tokio::time::timeout(timeout, connecting)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+/// speak SOCKS5 with a client, then echo or relay its connection,
+/// returning the count of bytes received from it afterwards
+pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_address: SocketAddr,
+    server: &Server,
+) -> io::Result<u64> {
+    // options
+    let config = &server.config;
+
+    // the whole negotiation is bounded, like the authentication challenge
+    let timeout = Duration::from_millis(config.auth_timeout);
+    let target = tokio::time::timeout(timeout, negotiate(stream, client_address, server))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))?;
+
+    // answered here, without any upstream
+    if target.matches(&config.socks_echo_target) {
+        if !config.quiet {
+            println!("SOCKS client {:?} connected to the echo", client_address);
+        }
+        reply(stream, SUCCEEDED, unspecified()).await?;
+        return match config.copy_mode {
+            CopyMode::Loop => echo::echo_loop(stream, client_address, config.quiet).await,
+            _ => echo::echo_buffered(stream, config.buffer_size).await,
+        };
+    }
+
+    // not an open proxy unless asked to
+    if !config.socks_upstream {
+        reply(stream, NOT_ALLOWED, unspecified()).await?;
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("SOCKS connection to {} not allowed", target),
+        ));
+    }
+
+    // relay to the real target
+    let timeout = Duration::from_millis(config.socks_connect_timeout);
+    let mut upstream = match connect(&target, timeout).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            reply(stream, failure_code(&e), unspecified()).await?;
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to connect to {}: {}", target, e),
+            ));
+        }
+    };
+    if !config.quiet {
+        println!("SOCKS client {:?} connected to {}", client_address, target);
+    }
+    reply(stream, SUCCEEDED, upstream.local_addr()?).await?;
+
+    // both ways until both sides are done
+    let (sent, _) = tokio::io::copy_bidirectional(stream, &mut upstream).await?;
+
+    Ok(sent)
+}
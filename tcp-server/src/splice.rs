@@ -0,0 +1,101 @@
+// zero-copy echo with splice(2): socket -> pipe -> socket, bytes never reach user space
+
+// use tokio for async runtime
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+
+// types for raw file descriptors and I/O results
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// a pipe used as the in-kernel buffer
+struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl Pipe {
+    /// create a non-blocking pipe, asking the kernel for `size` bytes of capacity
+    fn new(size: usize) -> io::Result<Self> {
+        // read and write ends
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // the descriptors are ours from now on
+        let pipe = unsafe {
+            Pipe {
+                read: OwnedFd::from_raw_fd(fds[0]),
+                write: OwnedFd::from_raw_fd(fds[1]),
+            }
+        };
+
+        // best effort, the kernel caps it at /proc/sys/fs/pipe-max-size
+        unsafe {
+            libc::fcntl(
+                pipe.write.as_raw_fd(),
+                libc::F_SETPIPE_SZ,
+                size as libc::c_int,
+            )
+        };
+
+        Ok(pipe)
+    }
+}
+
+/// move up to `len` bytes from `from` to `to` without blocking
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    // both ends are non-blocking
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    let n = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            flags,
+        )
+    };
+
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+/// echo until the client shuts down its write half, moving up to `chunk_size` bytes at a time
+pub async fn echo(socket: &TcpStream, chunk_size: usize) -> io::Result<u64> {
+    // in-kernel buffer
+    let pipe = Pipe::new(chunk_size)?;
+    // descriptor of the connection
+    let fd = socket.as_raw_fd();
+    // total count of received bytes
+    let mut echoed_bytes_count: u64 = 0;
+
+    loop {
+        // fill the pipe from the socket, the pipe is empty at this point
+        let n = socket
+            .async_io(Interest::READABLE, || {
+                splice(fd, pipe.write.as_raw_fd(), chunk_size)
+            })
+            .await?;
+        // client finished sending
+        if n == 0 {
+            return Ok(echoed_bytes_count);
+        }
+        // add to sum
+        echoed_bytes_count += n as u64;
+
+        // drain the pipe into the socket
+        let mut left = n;
+        while left > 0 {
+            left -= socket
+                .async_io(Interest::WRITABLE, || {
+                    splice(pipe.read.as_raw_fd(), fd, left)
+                })
+                .await?;
+        }
+    }
+}
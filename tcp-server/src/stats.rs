@@ -0,0 +1,140 @@
+// aggregate statistics per client IP, kept in memory and snapshotted to a JSON file
+
+// use serde to read and write snapshots
+use serde::{Deserialize, Serialize};
+
+// use anyhow for error handling
+use anyhow::{Context, Result};
+
+// standard library types
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// what is known about one client
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientStats {
+    /// connections accepted
+    pub sessions: u64,
+    /// bytes echoed over all sessions
+    pub bytes: u64,
+    /// sessions that ended with an error
+    pub errors: u64,
+    /// first connection, in seconds since the Unix epoch
+    pub first_seen: u64,
+    /// last connection, in seconds since the Unix epoch
+    pub last_seen: u64,
+}
+
+/// statistics of all clients
+#[derive(Debug, Default)]
+pub struct Stats {
+    clients: Mutex<BTreeMap<IpAddr, ClientStats>>,
+}
+
+impl Stats {
+    /// load a snapshot, starting empty when the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        // nothing saved yet
+        if !path.exists() {
+            return Ok(Stats::default());
+        }
+        // parse the snapshot
+        let snapshot = std::fs::read(path)
+            .with_context(|| format!("Failed to read statistics from {}", path.display()))?;
+        let clients = serde_json::from_slice(&snapshot)
+            .with_context(|| format!("Invalid statistics in {}", path.display()))?;
+
+        Ok(Stats {
+            clients: Mutex::new(clients),
+        })
+    }
+
+    /// record a finished session, with the echoed bytes or `None` on error
+    pub fn record(&self, ip: IpAddr, echoed_bytes: Option<u64>) {
+        // seconds since the Unix epoch
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        // update this client
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients.entry(ip).or_insert_with(|| ClientStats {
+            first_seen: now,
+            ..ClientStats::default()
+        });
+        client.sessions += 1;
+        client.last_seen = now;
+        match echoed_bytes {
+            Some(bytes) => client.bytes += bytes,
+            None => client.errors += 1,
+        }
+    }
+
+    /// statistics of one client
+    pub fn get(&self, ip: IpAddr) -> Option<ClientStats> {
+        self.clients.lock().unwrap().get(&ip).cloned()
+    }
+
+    /// statistics of every client
+    pub fn snapshot(&self) -> BTreeMap<IpAddr, ClientStats> {
+        self.clients.lock().unwrap().clone()
+    }
+
+    /// write a snapshot, replacing the file atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        // serialize outside of the file system
+        let snapshot = serde_json::to_vec_pretty(&self.snapshot())?;
+
+        // write next to the target, then move over it
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        std::fs::write(&temporary, snapshot)
+            .with_context(|| format!("Failed to write statistics to {}", temporary.display()))?;
+        std::fs::rename(&temporary, path)
+            .with_context(|| format!("Failed to write statistics to {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// save a snapshot every `interval`
+pub async fn persist(stats: Arc<Stats>, path: PathBuf, interval: Duration) {
+    // tick at the given interval, the first tick is immediate
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        // wait for next tick
+        ticker.tick().await;
+        // save
+        if let Err(e) = stats.save(&path) {
+            println!("{:#}", e);
+        }
+    }
+}
+
+/// print saved statistics, of one client or all of them
+pub fn print(path: &Path, client: Option<IpAddr>) -> Result<()> {
+    // read what the server saved last
+    let stats = Stats::load(path)?;
+    // clients to show
+    let clients: Vec<(IpAddr, ClientStats)> = match client {
+        Some(ip) => stats.get(ip).map(|stats| (ip, stats)).into_iter().collect(),
+        None => stats.snapshot().into_iter().collect(),
+    };
+
+    // one line per client
+    for (ip, client) in clients {
+        println!(
+            "{} sessions={} bytes={} errors={} first_seen={} last_seen={}",
+            ip, client.sessions, client.bytes, client.errors, client.first_seen, client.last_seen
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+// integration with systemd: socket activation, readiness and watchdog
+//
+// see sd_listen_fds(3), sd_notify(3) and sd_watchdog_enabled(3)
+
+// use socket2 to check inherited sockets
+use socket2::{Socket, Type};
+
+// use anyhow for error handling
+use anyhow::{bail, Context, Result};
+
+// standard library types
+use std::env;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// first file descriptor passed by systemd
+const LISTEN_FDS_START: i32 = 3;
+
+/// take the listening sockets passed by systemd, empty when not socket activated
+///
+/// this clears variables of the environment, so it must be called before any thread starts
+pub fn listen_fds() -> Result<Vec<std::net::TcpListener>> {
+    // the variables are only meant for this process
+    if env::var("LISTEN_PID").map(is_this_process) != Ok(true) {
+        return Ok(Vec::new());
+    }
+    // number of passed descriptors
+    let count: i32 = match env::var("LISTEN_FDS") {
+        Ok(count) => count.parse().context("Invalid LISTEN_FDS")?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    // do not pass the descriptors on to child processes
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // take ownership of every descriptor
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // systemd hands them over to us, nobody else owns them
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+
+            // only stream sockets can be served
+            if socket.r#type().context("Invalid socket from systemd")? != Type::STREAM {
+                bail!("Socket {} from systemd is not a stream socket", fd);
+            }
+            // tokio requires non-blocking sockets
+            socket
+                .set_nonblocking(true)
+                .context("Failed to set non-blocking mode")?;
+
+            Ok(socket.into())
+        })
+        .collect()
+}
+
+/// send a state change to systemd, does nothing when not run by systemd
+pub fn notify(state: &str) -> Result<()> {
+    // socket to send notifications to
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+    // notifications are single datagrams
+    let socket = UnixDatagram::unbound().context("Failed to create notification socket")?;
+
+    // a leading '@' means a socket in the abstract namespace
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            // use abstract addresses
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+
+            // send to abstract address
+            let address =
+                SocketAddr::from_abstract_name(name).context("Invalid NOTIFY_SOCKET address")?;
+            socket.send_to_addr(state.as_bytes(), &address)
+        }
+        _ => socket.send_to(state.as_bytes(), &path),
+    }
+    .with_context(|| format!("Failed to notify systemd of {}", state))?;
+
+    Ok(())
+}
+
+/// interval at which systemd expects watchdog pings, if enabled
+pub fn watchdog_interval() -> Option<Duration> {
+    // the watchdog may be meant for another process
+    if env::var("WATCHDOG_PID").map(is_this_process) == Ok(false) {
+        return None;
+    }
+    // timeout before systemd considers the service hung, 0 disables the watchdog
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    // ping twice per timeout as recommended
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// ping the watchdog forever
+pub async fn watchdog(interval: Duration) {
+    // tick at the given interval
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        // wait for next tick
+        ticker.tick().await;
+        // ping
+        if let Err(e) = notify("WATCHDOG=1") {
+            println!("{:#}", e);
+        }
+    }
+}
+
+/// whether the value of a `*_PID` variable names this process
+fn is_this_process(pid: String) -> bool {
+    pid.parse() == Ok(std::process::id())
+}
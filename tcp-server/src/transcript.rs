@@ -0,0 +1,99 @@
+// running BLAKE2 hash of everything exchanged on a connection
+//
+// the transcript hash is BLAKE2b-256(BLAKE2b-256(received) ++ BLAKE2b-256(sent)),
+// so a client can recompute it from what it sent and what it got back
+
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// use blake2 for hashing
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+
+// types for I/O results and polling
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// BLAKE2b with a 256-bit output, as used by Substrate
+pub type Blake2b256 = Blake2b<U32>;
+
+/// hash of both directions of a session
+pub fn transcript_hash(received: &[u8], sent: &[u8]) -> [u8; 32] {
+    combine(
+        Blake2b256::new().chain_update(received),
+        Blake2b256::new().chain_update(sent),
+    )
+}
+
+/// combine the hashes of both directions
+fn combine(received: Blake2b256, sent: Blake2b256) -> [u8; 32] {
+    Blake2b256::new()
+        .chain_update(received.finalize())
+        .chain_update(sent.finalize())
+        .finalize()
+        .into()
+}
+
+/// stream hashing the bytes read from and written to it
+pub struct Recorded<S> {
+    inner: S,
+    received: Blake2b256,
+    sent: Blake2b256,
+}
+
+impl<S> Recorded<S> {
+    /// start an empty transcript
+    pub fn new(inner: S) -> Self {
+        Recorded {
+            inner,
+            received: Blake2b256::new(),
+            sent: Blake2b256::new(),
+        }
+    }
+
+    /// hash of the transcript so far
+    pub fn finish(self) -> [u8; 32] {
+        combine(self.received, self.sent)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Recorded<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // remember how much was there before
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        // hash what was added
+        self.received.update(&buf.filled()[before..]);
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorded<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // hash only what was actually written
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            self.sent.update(&buf[..n]);
+        }
+
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
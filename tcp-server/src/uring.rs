@@ -0,0 +1,162 @@
+// io_uring backend: the echo of every connection goes through the ring of its thread
+//
+// connections are accepted on the listeners of the epoll path, with the same socket
+// options and systemd activation, then every read and write is an io_uring operation;
+// tokio-uring cannot take over an existing listening socket, so accepting stays on epoll
+
+// use tokio for accepting and tokio-uring for the echo
+use tokio::net::TcpListener;
+use tokio_uring::buf::IoBuf;
+use tokio_uring::net::TcpStream;
+
+// use anyhow for error handling
+use anyhow::{Context, Result};
+
+// types for I/O results, socket address and sharing
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+use std::sync::Arc;
+
+// accepting and echoing connections
+use crate::server::Server;
+
+/// echo chunk by chunk through the ring until the client shuts down its write half
+pub async fn echo(
+    stream: &TcpStream,
+    client_address: SocketAddr,
+    buffer_size: usize,
+    quiet: bool,
+) -> io::Result<u64> {
+    // the ring owns the buffer while an operation is in flight
+    let mut buffer = vec![0u8; buffer_size];
+    // total count of received bytes
+    let mut echoed_bytes_count: u64 = 0;
+
+    loop {
+        // client finished sending
+        let (result, read) = stream.read(buffer).await;
+        let n = match result? {
+            0 => return Ok(echoed_bytes_count),
+            n => n,
+        };
+        // add to sum
+        echoed_bytes_count += n as u64;
+
+        // print to screen
+        if !quiet {
+            println!(
+                "From {:?}: {}",
+                client_address,
+                String::from_utf8_lossy(&read[..n])
+            );
+        }
+
+        // send the chunk back, then take the buffer back for the next read
+        let (result, written) = stream.write_all(read.slice(..n)).await;
+        result?;
+        buffer = written.into_inner();
+    }
+}
+
+/// hand an accepted connection over to the ring
+fn adopt(socket: tokio::net::TcpStream) -> io::Result<TcpStream> {
+    // the ring waits for readiness itself, blocking mode keeps it from seeing EAGAIN
+    let socket = socket.into_std()?;
+    socket.set_nonblocking(false)?;
+
+    Ok(TcpStream::from_std(socket))
+}
+
+/// echo one connection and shut it down
+async fn handle_client(
+    socket: tokio::net::TcpStream,
+    client_address: SocketAddr,
+    server: &Server,
+) -> Result<u64> {
+    // options
+    let config = &server.config;
+
+    let stream = adopt(socket).context("Failed to hand connection over to io_uring")?;
+    let echoed_bytes = echo(&stream, client_address, config.buffer_size, config.quiet)
+        .await
+        .with_context(|| format!("Failed to echo from {:?}", client_address))?;
+    // tell the client we are done as well
+    stream
+        .shutdown(Shutdown::Write)
+        .context("Failed to shut down connection")?;
+
+    Ok(echoed_bytes)
+}
+
+/// accept connections on a listener and echo them through the ring of this thread
+pub async fn serve(listener: TcpListener, server: Arc<Server>) {
+    loop {
+        // try to accept an incoming connection
+        match listener.accept().await {
+            // when connection established
+            Ok((socket, client_address)) => {
+                // tune the accepted connection
+                if let Err(e) = server.config.socket.apply_stream(&socket) {
+                    println!(
+                        "Failed to configure connection from {:?}: {:#}",
+                        client_address, e
+                    );
+                }
+
+                // share state with the new task
+                let server = server.clone();
+                // ring operations stay on this thread
+                tokio_uring::spawn(async move {
+                    println!("New connection from {:?}", client_address);
+                    let result = handle_client(socket, client_address, &server).await;
+                    // account for the session
+                    server
+                        .stats
+                        .record(client_address.ip(), result.as_ref().ok().copied());
+
+                    match result {
+                        // connection closed
+                        Ok(echoed_bytes) => println!(
+                            "Connection from {:?} closed, with {} bytes echoed",
+                            client_address, echoed_bytes
+                        ),
+                        // error happened
+                        Err(e) => println!("{:#}", e),
+                    }
+                });
+            }
+            // when connection failed to be established
+            Err(e) => println!("Failed to establish a connection: {}", e),
+        }
+    }
+}
+
+/// serve `listeners` on the calling thread with an io_uring runtime, until the process exits
+pub fn run_thread(server: Arc<Server>, listeners: Vec<std::net::TcpListener>, core: usize) {
+    // a ring of its own, for this thread only
+    let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            println!("Failed to start io_uring runtime on core {}: {}", core, e);
+            return;
+        }
+    };
+
+    runtime.block_on(async move {
+        for listener in listeners {
+            // hand the socket over to tokio
+            match TcpListener::from_std(listener) {
+                Ok(listener) => {
+                    if let Ok(address) = listener.local_addr() {
+                        println!("Server listening on {} (core {}, io_uring)", address, core);
+                    }
+                    // serve it on this thread
+                    tokio_uring::spawn(serve(listener, server.clone()));
+                }
+                Err(e) => println!("Failed to initialize TCP server: {}", e),
+            }
+        }
+        // run until the process exits
+        std::future::pending::<()>().await
+    });
+}
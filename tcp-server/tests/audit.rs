@@ -0,0 +1,316 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for addresses, file paths and timing
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use substrate_course_task_2::audit::{CloseReason, Entry, Transport};
+use substrate_course_task_2::{admin, Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// never returns
+const SPIN: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const 0)))
+"#;
+
+/// audit log in the temporary directory, unique to this test
+fn audit_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("echo-audit-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// entries in the log at `path`
+fn read_entries(path: &Path) -> Vec<Entry> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// wait until the log at `path` has `count` entries
+async fn wait_for(path: &Path, count: usize) -> Vec<Entry> {
+    for _ in 0..200 {
+        let entries = read_entries(path);
+        if entries.len() >= count {
+            return entries;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("audit log has {} entries", read_entries(path).len());
+}
+
+/// send `payload`, shut down and return everything answered
+async fn session(address: SocketAddr, payload: &[u8]) -> (SocketAddr, Vec<u8>) {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    let peer = client.local_addr().unwrap();
+    client.write_all(payload).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut answer = Vec::new();
+    // the server may reset the connection
+    let _ = client.read_to_end(&mut answer).await;
+    (peer, answer)
+}
+
+#[tokio::test]
+async fn records_finished_connections() {
+    let path = audit_path("finished");
+    let (address, _) = start_server(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        "--copy-mode",
+        "buffered",
+    ])
+    .await;
+
+    let (peer, answer) = session(address, b"hello").await;
+    assert_eq!(answer, b"hello");
+    let (second_peer, _) = session(address, b"world!").await;
+
+    // in order, with what went over the wire
+    let entries = wait_for(&path, 2).await;
+    assert_eq!(entries[0].id, 1);
+    assert_eq!(entries[0].transport, Transport::Tcp);
+    assert_eq!(entries[0].peer, peer);
+    assert_eq!(entries[0].local, Some(address));
+    assert_eq!(entries[0].tls_identity, None);
+    assert_eq!(entries[0].reason, CloseReason::ClientEof);
+    assert_eq!(entries[0].error, None);
+    assert_eq!(entries[0].bytes_received, 5);
+    assert_eq!(entries[0].bytes_sent, 5);
+    assert!(entries[0].accepted <= entries[0].closed);
+    assert_eq!(entries[1].id, 2);
+    assert_eq!(entries[1].peer, second_peer);
+    assert_eq!(entries[1].bytes_received, 6);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn tells_timeouts_from_errors() {
+    let path = audit_path("reasons");
+    let (address, _) = start_server(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        "--auth-key",
+        "secret",
+        "--auth-timeout",
+        "100",
+    ])
+    .await;
+
+    // no answer to the challenge
+    let mut client = TcpStream::connect(address).await.unwrap();
+    let mut challenge = Vec::new();
+    client.read_to_end(&mut challenge).await.unwrap();
+    let entries = wait_for(&path, 1).await;
+    assert_eq!(entries[0].reason, CloseReason::Timeout);
+    assert!(entries[0].error.as_ref().unwrap().contains("no answer"));
+    assert_eq!(entries[0].bytes_sent, challenge.len() as u64);
+
+    // a wrong answer
+    session(address, b"0000\n").await;
+    let entries = wait_for(&path, 2).await;
+    assert_eq!(entries[1].reason, CloseReason::Error);
+    assert_eq!(entries[1].bytes_received, 5);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn records_plugins_out_of_fuel_as_limits() {
+    let path = audit_path("limit");
+    let plugin = audit_path("limit.wat");
+    std::fs::write(&plugin, SPIN).unwrap();
+    let (address, _) = start_server(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        "--wasm-plugin",
+        plugin.to_str().unwrap(),
+        "--wasm-fuel",
+        "1000",
+    ])
+    .await;
+
+    session(address, b"spin").await;
+    let entries = wait_for(&path, 1).await;
+    assert_eq!(entries[0].reason, CloseReason::Limit);
+
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(plugin).unwrap();
+}
+
+#[tokio::test]
+async fn records_open_connections_at_shutdown() {
+    let path = audit_path("shutdown");
+    let (address, server) = start_server(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        "--copy-mode",
+        "buffered",
+    ])
+    .await;
+
+    // still open when the server stops
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(b"hi").await.unwrap();
+    let mut echoed = [0u8; 2];
+    client.read_exact(&mut echoed).await.unwrap();
+    server.audit.as_ref().unwrap().shutdown();
+    let entries = wait_for(&path, 1).await;
+    assert_eq!(entries[0].reason, CloseReason::Shutdown);
+    assert_eq!(entries[0].bytes_received, 2);
+
+    // written once only
+    client.shutdown().await.unwrap();
+    client.read_to_end(&mut Vec::new()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(read_entries(&path).len(), 1);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn records_connections_killed_by_an_administrator() {
+    let path = audit_path("kill");
+    let (address, server) = start_server(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        "--copy-mode",
+        "buffered",
+    ])
+    .await;
+    let audit = server.audit.clone().unwrap();
+    // the admin interface on a port of its own
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_address = listener.local_addr().unwrap();
+    tokio::spawn(admin::serve(listener, audit.clone()));
+
+    // a connection that would stay open
+    let mut client = TcpStream::connect(address).await.unwrap();
+    let peer = client.local_addr().unwrap();
+    client.write_all(b"hi").await.unwrap();
+    let mut echoed = [0u8; 2];
+    client.read_exact(&mut echoed).await.unwrap();
+
+    // listed, then killed
+    let mut admin = TcpStream::connect(admin_address).await.unwrap();
+    admin
+        .write_all(b"sessions\nKILL 1\nkill 1\nkill x\n")
+        .await
+        .unwrap();
+    admin.shutdown().await.unwrap();
+    let mut answers = String::new();
+    admin.read_to_string(&mut answers).await.unwrap();
+    assert_eq!(
+        answers,
+        format!(
+            "1 tcp {} 2 2\nOK\nOK\nERR no such connection\nERR invalid connection id\n",
+            peer
+        )
+    );
+
+    // the client sees the connection end
+    let rest = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut Vec::new()))
+        .await
+        .unwrap();
+    assert!(matches!(rest, Ok(0) | Err(_)));
+    let entries = wait_for(&path, 1).await;
+    assert_eq!(entries[0].reason, CloseReason::AdminKill);
+    assert_eq!(entries[0].bytes_received, 2);
+    assert!(audit.open_sessions().is_empty());
+
+    // written once only
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(read_entries(&path).len(), 1);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn appends_and_rotates_by_size() {
+    let path = audit_path("rotate");
+    // left by an earlier run
+    let earlier = Entry {
+        id: 1,
+        transport: Transport::Tcp,
+        accepted: 0,
+        closed: 0,
+        peer: "127.0.0.1:1".parse().unwrap(),
+        local: None,
+        tls_identity: None,
+        reason: CloseReason::Error,
+        error: Some("earlier".to_string()),
+        bytes_received: 0,
+        bytes_sent: 0,
+    };
+    std::fs::write(&path, serde_json::to_string(&earlier).unwrap() + "\n").unwrap();
+    let (address, server) = start_server(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        "--audit-max-size",
+        "600",
+        "--audit-files",
+        "1",
+    ])
+    .await;
+
+    // what was there is kept until rotated away
+    session(address, b"hello").await;
+    let entries = wait_for(&path, 2).await;
+    assert_eq!(entries[0], earlier);
+
+    // an entry is about 250 bytes, so files hold two of them
+    for _ in 0..5 {
+        session(address, b"hello").await;
+    }
+    let rotated = server.audit.as_ref().unwrap().rotated_path(1);
+    for _ in 0..200 {
+        if read_entries(&path).iter().any(|entry| entry.id == 6) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let current = read_entries(&path);
+    let previous = read_entries(&rotated);
+    assert!(std::fs::metadata(&path).unwrap().len() <= 600);
+    assert!(std::fs::metadata(&rotated).unwrap().len() <= 600);
+    assert_eq!(previous.last().unwrap().id + 1, current[0].id);
+    assert!(!server.audit.as_ref().unwrap().rotated_path(2).exists());
+
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(rotated).unwrap();
+}
+
+#[test]
+fn audit_log_counts_bytes_in_user_space() {
+    let config = Config::parse_from([
+        "server",
+        "--audit-log",
+        "audit.jsonl",
+        "--copy-mode",
+        "splice",
+    ]);
+    assert!(Server::new(config).is_err());
+}
+
+#[test]
+fn admin_interface_needs_an_audit_log() {
+    // connections to kill are found in the log
+    assert!(Config::try_parse_from(["server", "--admin", "127.0.0.1:7000"]).is_err());
+}
@@ -0,0 +1,123 @@
+// use tokio for async runtime
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpSocket, TcpStream};
+
+// types for addresses and timing
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use substrate_course_task_2::auth::authenticate;
+use substrate_course_task_2::Server;
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// failed attempts recorded for the loopback address
+fn loopback_failures(server: &Server) -> u64 {
+    server
+        .auth
+        .as_ref()
+        .unwrap()
+        .failures(IpAddr::from([127, 0, 0, 1]))
+}
+
+/// fail to authenticate from `from`, waiting until the server closed the connection
+async fn fail_from(from: IpAddr, address: SocketAddr) {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(SocketAddr::new(from, 0)).unwrap();
+    let mut client = socket.connect(address).await.unwrap();
+    assert!(authenticate(&mut client, b"guess").await.is_err());
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).await.unwrap();
+}
+
+#[tokio::test]
+async fn echoes_after_successful_handshake() {
+    let (address, server) = start_server(&["--auth-key", "secret"]).await;
+    let mut client = TcpStream::connect(address).await.unwrap();
+
+    authenticate(&mut client, b"secret").await.unwrap();
+    client.write_all(b"authenticated").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(echoed, b"authenticated");
+    assert_eq!(loopback_failures(&server), 0);
+}
+
+#[tokio::test]
+async fn denies_and_counts_wrong_key() {
+    let (address, server) = start_server(&["--auth-key", "secret"]).await;
+
+    for attempt in 1..=2 {
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let error = authenticate(&mut client, b"guess").await.unwrap_err();
+        assert!(error.to_string().contains("DENIED"));
+
+        // nothing is echoed afterwards
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(loopback_failures(&server), attempt);
+    }
+}
+
+#[tokio::test]
+async fn accepts_any_key_from_token_file() {
+    let tokens = std::env::temp_dir().join(format!("echo-tokens-{}", std::process::id()));
+    std::fs::write(&tokens, "# test tokens\nfirst\n\n  second  \n").unwrap();
+    let (address, _server) = start_server(&["--auth-tokens", tokens.to_str().unwrap()]).await;
+
+    for key in [&b"first"[..], b"second"] {
+        let mut client = TcpStream::connect(address).await.unwrap();
+        authenticate(&mut client, key).await.unwrap();
+    }
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert!(authenticate(&mut client, b"# test tokens").await.is_err());
+
+    std::fs::remove_file(tokens).unwrap();
+}
+
+#[tokio::test]
+async fn denies_clients_that_do_not_answer_in_time() {
+    let (address, server) = start_server(&["--auth-key", "secret", "--auth-timeout", "100"]).await;
+    let client = TcpStream::connect(address).await.unwrap();
+    let mut lines = BufReader::new(client).lines();
+
+    // challenge, then silence until the server gives up
+    assert!(lines
+        .next_line()
+        .await
+        .unwrap()
+        .unwrap()
+        .starts_with("AUTH "));
+    assert_eq!(lines.next_line().await.unwrap().unwrap(), "DENIED");
+    assert_eq!(lines.next_line().await.unwrap(), None);
+    assert_eq!(loopback_failures(&server), 1);
+}
+
+#[tokio::test]
+async fn forgets_failures_after_a_quiet_window() {
+    let (address, server) =
+        start_server(&["--auth-key", "secret", "--auth-failure-window", "200"]).await;
+    let auth = server.auth.as_ref().unwrap();
+    let loopback = IpAddr::from([127, 0, 0, 1]);
+    let other = IpAddr::from([127, 0, 0, 2]);
+
+    // counted while recent
+    fail_from(loopback, address).await;
+    fail_from(other, address).await;
+    assert_eq!(auth.failures(loopback), 1);
+    assert_eq!(auth.failing_addresses(), 2);
+
+    // forgotten once quiet for a whole window, and counted from scratch again
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(auth.failures(other), 0);
+    fail_from(loopback, address).await;
+    assert_eq!(auth.failures(loopback), 1);
+
+    // addresses quiet since are swept out
+    assert_eq!(auth.failing_addresses(), 1);
+}
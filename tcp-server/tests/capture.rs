@@ -0,0 +1,283 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for conversions, addresses, file paths and timing
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use substrate_course_task_2::capture::checksum;
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server_on;
+
+/// TCP flags
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const ACK: u8 = 0x10;
+
+/// a parsed TCP segment
+#[derive(Debug)]
+struct Segment {
+    source_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+/// capture file in the temporary directory, unique to this test
+fn capture_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("echo-capture-{}-{}", name, std::process::id()))
+}
+
+/// echo `payload` in one session
+async fn session(address: SocketAddr, payload: &[u8]) {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(payload).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(echoed, payload);
+}
+
+/// packets of a pcap file
+fn read_pcap(file: &[u8]) -> Vec<Vec<u8>> {
+    // little endian, microseconds, raw IP
+    assert_eq!(file[..4], 0xa1b2_c3d4u32.to_le_bytes());
+    assert_eq!(file[20..24], 101u32.to_le_bytes());
+
+    let mut packets = Vec::new();
+    let mut rest = &file[24..];
+    while !rest.is_empty() {
+        let length = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+        packets.push(rest[16..16 + length].to_vec());
+        rest = &rest[16 + length..];
+    }
+    packets
+}
+
+/// packets of a pcapng file
+fn read_pcapng(file: &[u8]) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut rest = file;
+    while !rest.is_empty() {
+        let block_type = u32::from_le_bytes(rest[..4].try_into().unwrap());
+        let length = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        // trailing length repeats the leading one
+        assert_eq!(rest[length - 4..length], rest[4..8]);
+        match block_type {
+            // section header
+            0x0a0d_0d0a => assert_eq!(rest[8..12], 0x1a2b_3c4du32.to_le_bytes()),
+            // interface, raw IP
+            1 => assert_eq!(rest[8..10], 101u16.to_le_bytes()),
+            // enhanced packet
+            6 => {
+                let captured = u32::from_le_bytes(rest[20..24].try_into().unwrap()) as usize;
+                packets.push(rest[28..28 + captured].to_vec());
+            }
+            other => panic!("unexpected block type {:#x}", other),
+        }
+        rest = &rest[length..];
+    }
+    packets
+}
+
+/// check the headers of an IP packet and extract its TCP segment
+fn parse_packet(packet: &[u8]) -> Segment {
+    // IP header and pseudo header of the TCP checksum
+    let (segment, pseudo) = match packet[0] >> 4 {
+        4 => {
+            assert_eq!(checksum(&[&packet[..20]]), 0, "IPv4 checksum");
+            assert_eq!(packet[9], 6);
+            let total = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+            assert_eq!(total, packet.len());
+            let segment = &packet[20..];
+            let mut pseudo = packet[12..20].to_vec();
+            pseudo.extend_from_slice(&[0, 6]);
+            pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            (segment, pseudo)
+        }
+        6 => {
+            assert_eq!(packet[6], 6);
+            let segment = &packet[40..];
+            assert_eq!(
+                u16::from_be_bytes([packet[4], packet[5]]) as usize,
+                segment.len()
+            );
+            let mut pseudo = packet[8..40].to_vec();
+            pseudo.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 6]);
+            (segment, pseudo)
+        }
+        version => panic!("unexpected IP version {}", version),
+    };
+    assert_eq!(checksum(&[&pseudo, segment]), 0, "TCP checksum");
+
+    Segment {
+        source_port: u16::from_be_bytes([segment[0], segment[1]]),
+        seq: u32::from_be_bytes(segment[4..8].try_into().unwrap()),
+        ack: u32::from_be_bytes(segment[8..12].try_into().unwrap()),
+        flags: segment[13],
+        payload: segment[20..].to_vec(),
+    }
+}
+
+/// wait until the capture holds a whole session, ending with the client's last ACK
+async fn wait_for_close(path: &Path, read: fn(&[u8]) -> Vec<Vec<u8>>) -> Vec<Segment> {
+    for _ in 0..100 {
+        let segments: Vec<Segment> = read(&std::fs::read(path).unwrap())
+            .iter()
+            .map(|packet| parse_packet(packet))
+            .collect();
+        let closed = segments.iter().any(|segment| segment.flags == FIN | ACK)
+            && segments.last().map(|segment| segment.flags) == Some(ACK);
+        if closed && segments.len() > 3 {
+            return segments;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("session was not captured");
+}
+
+/// check a captured session follows TCP and carried `payload` both ways
+fn check_session(segments: &[Segment], client_port: u16, payload: &[u8]) {
+    // three-way handshake
+    assert_eq!(segments[0].flags, SYN);
+    assert_eq!(segments[1].flags, SYN | ACK);
+    assert_eq!(segments[1].ack, segments[0].seq.wrapping_add(1));
+    assert_eq!(segments[2].flags, ACK);
+
+    // each direction on its own
+    for from_client in [true, false] {
+        let direction: Vec<&Segment> = segments
+            .iter()
+            .filter(|segment| (segment.source_port == client_port) == from_client)
+            .collect();
+        // sequence numbers advance by payload, SYN and FIN
+        for pair in direction.windows(2) {
+            let advance =
+                pair[0].payload.len() as u32 + u32::from(pair[0].flags & (SYN | FIN) != 0);
+            assert_eq!(pair[1].seq, pair[0].seq.wrapping_add(advance));
+        }
+        // the echo is the payload
+        let data: Vec<u8> = direction
+            .iter()
+            .flat_map(|segment| segment.payload.clone())
+            .collect();
+        assert_eq!(data, payload);
+        // one FIN each
+        assert_eq!(
+            direction
+                .iter()
+                .filter(|segment| segment.flags & FIN != 0)
+                .count(),
+            1
+        );
+    }
+}
+
+#[tokio::test]
+async fn captures_sessions_as_tcp_streams() {
+    for (ip, name) in [("127.0.0.1", "v4"), ("::1", "v6")] {
+        // IPv6 may be disabled where tests run
+        if std::net::TcpListener::bind((ip, 0)).is_err() {
+            continue;
+        }
+        let path = capture_path(name);
+        let address = start_server_on(ip, &["--capture", path.to_str().unwrap()], None)
+            .await
+            .0;
+
+        // one session, larger than one synthesized segment
+        let payload: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let client_port = client.local_addr().unwrap().port();
+        client.write_all(&payload).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await.unwrap();
+
+        let segments = wait_for_close(&path, read_pcap).await;
+        check_session(&segments, client_port, &payload);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn writes_pcapng_when_asked_to() {
+    let path = capture_path("ng");
+    let address = start_server_on(
+        "127.0.0.1",
+        &[
+            "--capture",
+            path.to_str().unwrap(),
+            "--capture-format",
+            "pcapng",
+        ],
+        None,
+    )
+    .await
+    .0;
+
+    // odd sizes exercise block padding
+    let mut client = TcpStream::connect(address).await.unwrap();
+    let client_port = client.local_addr().unwrap().port();
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+
+    let segments = wait_for_close(&path, read_pcapng).await;
+    check_session(&segments, client_port, b"hello");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn rotates_capture_files_by_size() {
+    let path = capture_path("rotate");
+    let (address, server) = start_server_on(
+        "127.0.0.1",
+        &[
+            "--capture",
+            path.to_str().unwrap(),
+            "--capture-max-size",
+            "2000",
+            "--capture-files",
+            "2",
+        ],
+        None,
+    )
+    .await;
+
+    // far more than three files worth of traffic
+    for _ in 0..10 {
+        session(address, &[b'x'; 500]).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    server.capture.as_ref().unwrap().flush();
+
+    // the current file and two rotated ones, none beyond the limit
+    let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    for file in [path.clone(), rotated(1), rotated(2)] {
+        let size = std::fs::metadata(&file).unwrap().len();
+        assert!(size <= 2000, "{} has {} bytes", file.display(), size);
+        // each file stands on its own
+        read_pcap(&std::fs::read(&file).unwrap());
+        std::fs::remove_file(&file).unwrap();
+    }
+    assert!(!rotated(3).exists());
+}
+
+#[test]
+fn splice_cannot_be_captured() {
+    let config = Config::parse_from(["server", "--copy-mode", "splice", "--capture", "x.pcap"]);
+    assert!(Server::new(config).is_err());
+}
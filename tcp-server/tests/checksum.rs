@@ -0,0 +1,197 @@
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// types for addresses
+use std::net::SocketAddr;
+
+use substrate_course_task_2::checksum::{read_frame, write_frame, Algorithm, ChecksumMode};
+use substrate_course_task_2::client::{self, ClientOptions};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// client options for `frames` frames of up to `frame_size` bytes
+fn options(address: SocketAddr, frames: u64, frame_size: usize) -> ClientOptions {
+    ClientOptions {
+        address,
+        frames,
+        frame_size,
+    }
+}
+
+/// copy `reader` to `writer`, flipping the bits of the byte at `offset`
+async fn copy_flipping<R, W>(mut reader: R, mut writer: W, offset: Option<usize>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; 4096];
+    let mut position = 0;
+    loop {
+        let n = match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if let Some(offset) = offset.filter(|&o| o >= position && o < position + n) {
+            buffer[offset - position] ^= 0xff;
+        }
+        position += n;
+        if writer.write_all(&buffer[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// a middlebox in front of `server` corrupting one byte in each direction asked for
+async fn corrupting_proxy(
+    server: SocketAddr,
+    upstream: Option<usize>,
+    downstream: Option<usize>,
+) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (client, _) = listener.accept().await.unwrap();
+        let upstream_stream = TcpStream::connect(server).await.unwrap();
+        let (client_reader, client_writer) = client.into_split();
+        let (server_reader, server_writer) = upstream_stream.into_split();
+        tokio::join!(
+            copy_flipping(client_reader, server_writer, upstream),
+            copy_flipping(server_reader, client_writer, downstream),
+        );
+    });
+
+    address
+}
+
+#[test]
+fn computes_standard_checksums() {
+    assert_eq!(
+        Algorithm::Crc32.digest(b"123456789"),
+        hex::decode("cbf43926").unwrap()
+    );
+    assert_eq!(
+        Algorithm::Xxhash64.digest(b""),
+        hex::decode("ef46db3751d8e999").unwrap()
+    );
+}
+
+#[tokio::test]
+async fn appends_or_answers_with_checksums() {
+    // payload and CRC32 of each frame, even when sent back to back
+    let address = start_server(&["--protocol", "checksum"]).await.0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    write_frame(&mut client, &[b"hello"]).await.unwrap();
+    write_frame(&mut client, &[b"123456789"]).await.unwrap();
+    client.shutdown().await.unwrap();
+    let first = read_frame(&mut client).await.unwrap().unwrap();
+    assert_eq!(
+        first,
+        [&b"hello"[..], &crc32fast::hash(b"hello").to_be_bytes()].concat()
+    );
+    let second = read_frame(&mut client).await.unwrap().unwrap();
+    assert_eq!(second, hex::decode("313233343536373839cbf43926").unwrap());
+    assert_eq!(read_frame(&mut client).await.unwrap(), None);
+
+    // xxHash64 alone
+    let address = start_server(&[
+        "--protocol",
+        "checksum",
+        "--checksum",
+        "xxhash64",
+        "--checksum-mode",
+        "answer",
+    ])
+    .await
+    .0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    write_frame(&mut client, &[b""]).await.unwrap();
+    client.shutdown().await.unwrap();
+    let answer = read_frame(&mut client).await.unwrap().unwrap();
+    assert_eq!(answer, hex::decode("ef46db3751d8e999").unwrap());
+}
+
+#[tokio::test]
+async fn client_verifies_every_frame() {
+    for (algorithm, mode) in [
+        (Algorithm::Crc32, ChecksumMode::Append),
+        (Algorithm::Xxhash64, ChecksumMode::Answer),
+    ] {
+        let address = start_server(&[
+            "--protocol",
+            "checksum",
+            "--checksum",
+            if algorithm == Algorithm::Crc32 {
+                "crc32"
+            } else {
+                "xxhash64"
+            },
+            "--checksum-mode",
+            if mode == ChecksumMode::Append {
+                "append"
+            } else {
+                "answer"
+            },
+        ])
+        .await
+        .0;
+
+        // many frames in flight, larger than socket buffers in total
+        let report = client::run(algorithm, mode, &options(address, 500, 8192), true)
+            .await
+            .unwrap();
+        assert_eq!(report.frames, 500);
+        assert!(report.bytes >= 500);
+        assert_eq!(report.corrupted, 0);
+    }
+}
+
+#[tokio::test]
+async fn client_tells_which_way_frames_were_corrupted() {
+    let server = start_server(&["--protocol", "checksum"]).await.0;
+
+    // the first payload byte, right after the 4-byte length, in each direction; both
+    // ways, another byte so that the second flip does not undo the first
+    for (upstream, downstream) in [(Some(4), None), (None, Some(4)), (Some(4), Some(5))] {
+        let proxy = corrupting_proxy(server, upstream, downstream).await;
+        let report = client::run(
+            Algorithm::Crc32,
+            ChecksumMode::Append,
+            &options(proxy, 10, 100),
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.frames, 10);
+        assert_eq!(
+            (report.corrupted, report.upstream, report.downstream),
+            (1, upstream.is_some() as u64, downstream.is_some() as u64)
+        );
+    }
+}
+
+#[tokio::test]
+async fn refuses_frames_too_large_to_answer() {
+    let address = start_server(&["--protocol", "checksum"]).await.0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(&[0xff; 4]).await.unwrap();
+    // closed without an answer
+    let mut answer = Vec::new();
+    let _ = client.read_to_end(&mut answer).await;
+    assert!(answer.is_empty());
+
+    // the client would not be able to read the answers either
+    let error = client::run(
+        Algorithm::Crc32,
+        ChecksumMode::Append,
+        &options(address, 1, 0),
+        true,
+    )
+    .await
+    .unwrap_err();
+    assert!(error.to_string().contains("--frame-size"));
+}
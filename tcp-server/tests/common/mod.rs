@@ -0,0 +1,70 @@
+// helpers shared by the integration tests, each of which only uses some of them
+#![allow(dead_code)]
+
+// use tokio for async runtime
+use tokio::net::TcpListener;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for reading output, running the binary, addresses, file paths and sharing
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+use substrate_course_task_2::poe::Submitter;
+use substrate_course_task_2::{serve, Config, Server};
+
+/// start a quiet server on an ephemeral loopback port with extra command line options
+pub async fn start_server(args: &[&str]) -> (SocketAddr, Arc<Server>) {
+    start_server_on("127.0.0.1", args, None).await
+}
+
+/// start a quiet server on an ephemeral port of `ip` with extra command line options,
+/// anchoring transcripts with `anchor` if any
+pub async fn start_server_on(
+    ip: &str,
+    args: &[&str],
+    anchor: Option<Arc<dyn Submitter>>,
+) -> (SocketAddr, Arc<Server>) {
+    // parse options as the binary would
+    let config = Config::parse_from(["server", "--quiet"].iter().chain(args.iter()).copied());
+    let mut server = Server::new(config).unwrap();
+    if anchor.is_some() {
+        server.anchor = anchor;
+    }
+    let server = Arc::new(server);
+    // let the OS pick a free port
+    let listener = TcpListener::bind((ip, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // serve in the background
+    tokio::spawn(serve(listener, server.clone()));
+
+    (address, server)
+}
+
+/// start the server binary from `directory` on an ephemeral port,
+/// returning it with the address it listens on
+pub fn start_binary(directory: &Path, args: &[&str]) -> (Child, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_substrate-course-task-2"))
+        .args(["--quiet", "--listen", "127.0.0.1:0"])
+        .args(args)
+        .current_dir(directory)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // the address is announced once everything is set up
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Some(line) = lines.next() {
+        if let Some(address) = line.unwrap().strip_prefix("Server listening on ") {
+            // keep reading, the server fails to print into a closed pipe
+            std::thread::spawn(move || lines.for_each(drop));
+            return (child, address.to_string());
+        }
+    }
+    panic!("server did not start: {:?}", child.wait());
+}
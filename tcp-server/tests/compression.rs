@@ -0,0 +1,132 @@
+// use tokio for async runtime
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// use async-compression for the client side of compressed sessions
+use async_compression::tokio::bufread::{DeflateDecoder, ZstdDecoder};
+use async_compression::tokio::write::{DeflateEncoder, ZstdEncoder};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+use substrate_course_task_2::compression::Algorithm;
+use substrate_course_task_2::{handle_client, Config, Server, Summary};
+
+/// compressible payload
+fn payload() -> Vec<u8> {
+    b"the quick brown fox jumps over the lazy dog\n"
+        .iter()
+        .copied()
+        .cycle()
+        .take(256 * 1024)
+        .collect()
+}
+
+/// send `payload` through `encoder` and read back through `decoder` concurrently
+async fn exchange<E, D>(mut encoder: E, mut decoder: D, payload: Vec<u8>) -> Vec<u8>
+where
+    E: AsyncWrite + Unpin + Send + 'static,
+    D: AsyncRead + Unpin,
+{
+    let send = tokio::spawn(async move {
+        encoder.write_all(&payload).await.unwrap();
+        // finishes the compressed stream and shuts down our write half
+        encoder.shutdown().await.unwrap();
+    });
+    let mut echoed = Vec::new();
+    decoder.read_to_end(&mut echoed).await.unwrap();
+    send.await.unwrap();
+    echoed
+}
+
+/// run one compressed session against handle_client
+async fn session(algorithm: Algorithm) -> (Vec<u8>, Summary) {
+    let server = Server::new(Config::parse_from(["server", "--quiet", "--compression"])).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let handler = tokio::spawn(async move {
+        let (socket, client_address) = listener.accept().await.unwrap();
+        handle_client(socket, client_address, &server)
+            .await
+            .unwrap()
+    });
+
+    // announce the algorithm, then speak it
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_u8(algorithm.byte()).await.unwrap();
+    let (reader, writer) = client.into_split();
+    let reader = BufReader::new(reader);
+    let echoed = match algorithm {
+        Algorithm::None => exchange(writer, reader, payload()).await,
+        Algorithm::Deflate => {
+            exchange(
+                DeflateEncoder::new(writer),
+                DeflateDecoder::new(reader),
+                payload(),
+            )
+            .await
+        }
+        Algorithm::Zstd => {
+            exchange(
+                ZstdEncoder::new(writer),
+                ZstdDecoder::new(reader),
+                payload(),
+            )
+            .await
+        }
+    };
+
+    (echoed, handler.await.unwrap())
+}
+
+#[tokio::test]
+async fn echoes_uncompressed_when_asked_for_none() {
+    let (echoed, summary) = session(Algorithm::None).await;
+
+    assert!(echoed == payload());
+    let ratio = summary.compression.unwrap();
+    assert_eq!(ratio.compressed, ratio.uncompressed);
+}
+
+#[tokio::test]
+async fn echoes_through_deflate_and_records_ratio() {
+    let (echoed, summary) = session(Algorithm::Deflate).await;
+
+    assert!(echoed == payload());
+    assert_eq!(summary.echoed_bytes, payload().len() as u64);
+    let ratio = summary.compression.unwrap();
+    assert_eq!(ratio.uncompressed, summary.echoed_bytes);
+    assert!(ratio.ratio() > 10.0, "{:?}", ratio);
+}
+
+#[tokio::test]
+async fn echoes_through_zstd_and_records_ratio() {
+    let (echoed, summary) = session(Algorithm::Zstd).await;
+
+    assert!(echoed == payload());
+    let ratio = summary.compression.unwrap();
+    assert!(ratio.ratio() > 10.0, "{:?}", ratio);
+}
+
+#[tokio::test]
+async fn rejects_unknown_algorithm() {
+    let server = Server::new(Config::parse_from(["server", "--quiet", "--compression"])).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let handler = tokio::spawn(async move {
+        let (socket, client_address) = listener.accept().await.unwrap();
+        handle_client(socket, client_address, &server).await
+    });
+
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_u8(9).await.unwrap();
+
+    let error = handler.await.unwrap().unwrap_err();
+    assert!(format!("{:#}", error).contains("unknown compression algorithm 9"));
+}
+
+#[test]
+fn refuses_splice_with_compression() {
+    let config = Config::parse_from(["server", "--compression", "--copy-mode", "splice"]);
+    assert!(Server::new(config).is_err());
+}
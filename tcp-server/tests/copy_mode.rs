@@ -0,0 +1,59 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// send a few MiB through the server and check they come back unchanged
+async fn assert_echoes(copy_mode: &str) {
+    let address = start_server(&["--copy-mode", copy_mode]).await.0;
+    let client = TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = client.into_split();
+
+    // a payload that does not divide evenly into any buffer size
+    let payload: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| (i % 253) as u8).collect();
+    let expected = payload.clone();
+
+    // send and read back concurrently
+    let send = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+    let mut echoed = Vec::new();
+    reader.read_to_end(&mut echoed).await.unwrap();
+    send.await.unwrap();
+
+    assert_eq!(echoed.len(), expected.len());
+    assert!(echoed == expected);
+}
+
+#[tokio::test]
+async fn loop_mode_echoes_unchanged() {
+    assert_echoes("loop").await;
+}
+
+#[tokio::test]
+async fn buffered_mode_echoes_unchanged() {
+    assert_echoes("buffered").await;
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn splice_mode_echoes_unchanged() {
+    assert_echoes("splice").await;
+}
+
+#[test]
+fn refuses_empty_buffers() {
+    for copy_mode in ["buffered", "splice"] {
+        let config = Config::parse_from(["server", "--copy-mode", copy_mode, "--buffer-size", "0"]);
+        assert!(Server::new(config).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for timing
+use std::time::{Duration, Instant};
+
+use substrate_course_task_2::{handle_client, Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+#[tokio::test]
+async fn echoes_everything_before_closing_after_client_half_close() {
+    let address = start_server(&[]).await.0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+
+    // send in several pieces, then signal the end of input
+    client.write_all(b"hello ").await.unwrap();
+    client.write_all(b"half-closed ").await.unwrap();
+    client.write_all(b"world").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    // the server must echo all of it and then close its side
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(echoed, b"hello half-closed world");
+}
+
+#[tokio::test]
+async fn flushes_large_pending_echo_after_client_half_close() {
+    let address = start_server(&[]).await.0;
+    let client = TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = client.into_split();
+
+    // more than fits in the socket buffers, so echo is still pending on shutdown
+    let payload: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = payload.clone();
+
+    // send everything and shut down without reading in the meantime
+    let send = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+        writer
+    });
+
+    // read everything back
+    let mut echoed = Vec::new();
+    reader.read_to_end(&mut echoed).await.unwrap();
+    send.await.unwrap();
+
+    assert_eq!(echoed.len(), expected.len());
+    assert!(echoed == expected);
+}
+
+#[tokio::test]
+async fn closes_immediately_after_draining() {
+    let address = start_server(&[]).await.0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    // end of stream arrives right after the echo
+    let mut echoed = Vec::new();
+    tokio::time::timeout(Duration::from_secs(1), client.read_to_end(&mut echoed))
+        .await
+        .expect("server did not close its write half")
+        .unwrap();
+    assert_eq!(echoed, b"ping");
+}
+
+#[tokio::test]
+async fn keeps_write_half_open_for_linger_after_client_half_close() {
+    let address = start_server(&["--half-close-linger", "300"]).await.0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let shut_down_at = Instant::now();
+    client.shutdown().await.unwrap();
+
+    // the echo arrives right away
+    let mut echoed = [0u8; 4];
+    tokio::time::timeout(Duration::from_millis(200), client.read_exact(&mut echoed))
+        .await
+        .expect("echo waited for the linger period")
+        .unwrap();
+    assert_eq!(&echoed, b"ping");
+
+    // but the end of stream only after the linger period
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+    assert!(shut_down_at.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn keeps_echoing_pending_data_while_lingering() {
+    let address = start_server(&["--half-close-linger", "300"]).await.0;
+    let client = TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = client.into_split();
+
+    // more than fits in the socket buffers, so most is echoed after the shutdown
+    let payload: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = payload.clone();
+    let send = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+        Instant::now()
+    });
+
+    // everything comes back before the end of stream, which waits for the linger period
+    let mut echoed = Vec::new();
+    reader.read_to_end(&mut echoed).await.unwrap();
+    let shut_down_at = send.await.unwrap();
+    assert!(echoed == expected);
+    assert!(shut_down_at.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn reports_echoed_bytes_once_client_half_closes() {
+    let server = Server::new(Config::parse_from(["server"])).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // run handle_client directly on the accepted connection
+    let server = tokio::spawn(async move {
+        let (socket, client_address) = listener.accept().await.unwrap();
+        handle_client(socket, client_address, &server).await
+    });
+
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(b"0123456789").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+
+    assert_eq!(server.await.unwrap().unwrap().echoed_bytes, 10);
+    assert_eq!(echoed, b"0123456789");
+}
@@ -0,0 +1,174 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// types for addresses
+use std::net::SocketAddr;
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// send raw request bytes and read until the server closes
+async fn exchange(address: SocketAddr, request: &[u8]) -> String {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(request).await.unwrap();
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    String::from_utf8(response).unwrap()
+}
+
+#[tokio::test]
+async fn reflects_request_as_text() {
+    let address = start_server(&["--protocol", "http", "--http-format", "text"])
+        .await
+        .0;
+    let response = exchange(
+        address,
+        b"POST /echo?x=1 HTTP/1.1\r\nHost: test\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    )
+    .await;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Type: text/plain"));
+    assert!(head.contains("Connection: close"));
+    assert_eq!(
+        body,
+        "POST /echo?x=1 HTTP/1.1\r\nHost: test\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+    );
+}
+
+#[tokio::test]
+async fn reflects_request_as_json() {
+    let address = start_server(&["--protocol", "http", "--http-format", "json"])
+        .await
+        .0;
+    let response = exchange(
+        address,
+        b"PUT /items/7 HTTP/1.1\r\nX-Test: yes\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}",
+    )
+    .await;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Content-Type: application/json"));
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body["method"], "PUT");
+    assert_eq!(body["path"], "/items/7");
+    assert_eq!(body["version"], "HTTP/1.1");
+    assert_eq!(body["headers"][0], serde_json::json!(["X-Test", "yes"]));
+    assert_eq!(body["body"], "{}");
+}
+
+#[tokio::test]
+async fn keeps_connection_alive_for_pipelined_requests() {
+    let address = start_server(&["--protocol", "http", "--http-format", "text"])
+        .await
+        .0;
+    // two requests in one write, the second asks to close
+    let response = exchange(
+        address,
+        b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+    assert!(response.contains("Connection: keep-alive"));
+    assert!(response.contains("GET /first HTTP/1.1"));
+    assert!(response.contains("GET /second HTTP/1.1"));
+}
+
+#[tokio::test]
+async fn closes_http_1_0_connections_by_default() {
+    let address = start_server(&["--protocol", "http", "--http-format", "text"])
+        .await
+        .0;
+    let response = exchange(address, b"GET / HTTP/1.0\r\n\r\n").await;
+
+    assert!(response.contains("Connection: close"));
+    assert!(response.ends_with("GET / HTTP/1.0\r\n\r\n"));
+}
+
+#[tokio::test]
+async fn decodes_chunked_request_and_answers_chunked() {
+    let address = start_server(&["--protocol", "http", "--http-format", "json"])
+        .await
+        .0;
+    let response = exchange(
+        address,
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+          5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Trailer: done\r\n\r\n",
+    )
+    .await;
+
+    let (head, chunked) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Transfer-Encoding: chunked"));
+    assert!(!head.contains("Content-Length"));
+
+    // decode the single data chunk and the last chunk
+    let (size, rest) = chunked.split_once("\r\n").unwrap();
+    let size = usize::from_str_radix(size, 16).unwrap();
+    assert_eq!(&rest[size..], "\r\n0\r\n\r\n");
+
+    let body: serde_json::Value = serde_json::from_str(&rest[..size]).unwrap();
+    assert_eq!(body["body"], "hello, world");
+    assert!(body["headers"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!(["X-Trailer", "done"])));
+}
+
+#[tokio::test]
+async fn rejects_malformed_requests() {
+    let address = start_server(&["--protocol", "http", "--http-format", "text"])
+        .await
+        .0;
+    let response = exchange(address, b"NOT A REQUEST\r\n\r\n").await;
+
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[tokio::test]
+async fn refuses_huge_bodies() {
+    let address = start_server(&["--protocol", "http", "--http-format", "text"])
+        .await
+        .0;
+
+    // announced up front
+    let response = exchange(
+        address,
+        b"POST / HTTP/1.1\r\nContent-Length: 999999999999\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+    // a chunk size that would overflow the body size
+    let response = exchange(
+        address,
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+    // the server is still up
+    let response = exchange(address, b"GET / HTTP/1.0\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[tokio::test]
+async fn refuses_endless_trailers() {
+    let address = start_server(&["--protocol", "http", "--http-format", "text"])
+        .await
+        .0;
+
+    // as many trailers as headers fit in a request, on top of the headers
+    let mut request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n".to_vec();
+    for i in 0..64 {
+        request.extend_from_slice(format!("X-Trailer-{}: {}\r\n", i, i).as_bytes());
+    }
+    request.extend_from_slice(b"\r\n");
+    let response = exchange(address, &request).await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.ends_with("too many headers and trailers\n"));
+}
@@ -0,0 +1,121 @@
+// use tokio for async runtime
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for sharing and timing
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use substrate_course_task_2::command::{parse, Command, Mode};
+use substrate_course_task_2::{serve, Config, Server};
+
+/// connect to a fresh line protocol server
+async fn connect() -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+    // parse options as the binary would
+    let config = Config::parse_from(["server", "--quiet", "--protocol", "line"]);
+    // let the OS pick a free port
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    // serve in the background
+    tokio::spawn(serve(listener, Arc::new(Server::new(config).unwrap())));
+
+    let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+    (BufReader::new(reader).lines(), writer)
+}
+
+/// send a line and return the reply
+async fn send(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    writer: &mut OwnedWriteHalf,
+    line: &str,
+) -> String {
+    writer
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .unwrap();
+    lines.next_line().await.unwrap().unwrap()
+}
+
+#[test]
+fn parses_commands() {
+    assert_eq!(parse(b"STATS"), Ok(Command::Stats));
+    assert_eq!(parse(b"quit"), Ok(Command::Quit));
+    assert_eq!(parse(b"ECHO QUIT now"), Ok(Command::Echo(b"QUIT now")));
+    assert_eq!(
+        parse(b"DELAY 250"),
+        Ok(Command::Delay(Duration::from_millis(250)))
+    );
+    assert_eq!(parse(b"mode Reverse"), Ok(Command::Mode(Mode::Reverse)));
+    assert_eq!(parse(b"hello world"), Ok(Command::Text(b"hello world")));
+    assert_eq!(parse(b"STATS please"), Ok(Command::Text(b"STATS please")));
+    assert!(parse(b"DELAY soon").is_err());
+    assert!(parse(b"MODE sideways").is_err());
+    assert!(parse(b"MODE").is_err());
+}
+
+#[tokio::test]
+async fn echoes_plain_lines_and_applies_modes() {
+    let (mut lines, mut writer) = connect().await;
+
+    assert_eq!(send(&mut lines, &mut writer, "hello").await, "hello");
+    assert_eq!(
+        send(&mut lines, &mut writer, "MODE upper").await,
+        "OK mode upper"
+    );
+    assert_eq!(send(&mut lines, &mut writer, "hello").await, "HELLO");
+    assert_eq!(
+        send(&mut lines, &mut writer, "MODE reverse").await,
+        "OK mode reverse"
+    );
+    assert_eq!(send(&mut lines, &mut writer, "hello").await, "olleh");
+    assert_eq!(
+        send(&mut lines, &mut writer, "MODE hex").await,
+        "OK mode hex"
+    );
+    assert_eq!(send(&mut lines, &mut writer, "hi").await, "6869");
+    assert_eq!(
+        send(&mut lines, &mut writer, "ECHO MODE plain").await,
+        "4d4f444520706c61696e"
+    );
+    assert_eq!(
+        send(&mut lines, &mut writer, "MODE nope").await,
+        "ERR unknown mode: nope"
+    );
+}
+
+#[tokio::test]
+async fn reports_session_stats() {
+    let (mut lines, mut writer) = connect().await;
+
+    send(&mut lines, &mut writer, "abc").await;
+    send(&mut lines, &mut writer, "DELAY 5").await;
+    let stats = send(&mut lines, &mut writer, "STATS").await;
+
+    assert!(stats.starts_with("STATS lines=3 bytes=21 "), "{}", stats);
+    assert!(stats.ends_with("mode=plain delay_ms=5"), "{}", stats);
+}
+
+#[tokio::test]
+async fn delays_echoes() {
+    let (mut lines, mut writer) = connect().await;
+
+    assert_eq!(
+        send(&mut lines, &mut writer, "DELAY 200").await,
+        "OK delay 200ms"
+    );
+    let sent_at = Instant::now();
+    assert_eq!(send(&mut lines, &mut writer, "slow").await, "slow");
+    assert!(sent_at.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn quits_on_request() {
+    let (mut lines, mut writer) = connect().await;
+
+    assert_eq!(send(&mut lines, &mut writer, "QUIT").await, "BYE");
+    assert_eq!(lines.next_line().await.unwrap(), None);
+}
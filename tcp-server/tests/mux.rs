@@ -0,0 +1,327 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for streams by id, errors, polling and timing
+use std::collections::BTreeMap;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use substrate_course_task_2::mux::{serve_connection, Frame};
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// a writer that never takes a byte
+struct Stuck;
+
+impl AsyncWrite for Stuck {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(0))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// send frames in one write
+async fn send(client: &mut TcpStream, frames: &[Frame]) {
+    let mut bytes = Vec::new();
+    for frame in frames {
+        frame.encode(&mut bytes);
+    }
+    client.write_all(&bytes).await.unwrap();
+}
+
+/// read one frame, or `None` once the server closed the connection
+async fn receive(client: &mut TcpStream) -> Option<Frame> {
+    let mut buffer = vec![0; 9];
+    if client.read_exact(&mut buffer).await.is_err() {
+        return None;
+    }
+    // the payload of DATA frames follows
+    if buffer[0] == 1 {
+        let length = u32::from_be_bytes([buffer[5], buffer[6], buffer[7], buffer[8]]) as usize;
+        buffer.resize(9 + length, 0);
+        client.read_exact(&mut buffer[9..]).await.unwrap();
+    }
+    Frame::parse(&buffer, u32::MAX)
+        .unwrap()
+        .map(|(frame, _)| frame)
+}
+
+#[tokio::test]
+async fn echoes_interleaved_streams_independently() {
+    let address = start_server(&["--protocol", "mux", "--mux-window", "65536"])
+        .await
+        .0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+
+    // three streams sharing the connection
+    let payloads: BTreeMap<u32, Vec<u8>> = vec![
+        (1, b"hello".to_vec()),
+        (3, (0..100_000u32).map(|i| i as u8).collect()),
+        (7, b"world!".to_vec()),
+    ]
+    .into_iter()
+    .collect();
+    for id in payloads.keys() {
+        send(&mut client, &[Frame::Open(*id)]).await;
+    }
+
+    // send within the windows, reading echoes and grants as they come
+    let mut sent: BTreeMap<u32, usize> = payloads.keys().map(|id| (*id, 0)).collect();
+    let mut credit: BTreeMap<u32, usize> = payloads.keys().map(|id| (*id, 65536)).collect();
+    let mut echoed: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut opened = Vec::new();
+    let mut closed = Vec::new();
+    while closed.len() < payloads.len() {
+        // a little of every stream in turn
+        for (id, payload) in &payloads {
+            let n = (payload.len() - sent[id]).min(credit[id]).min(10_000);
+            if n > 0 {
+                let data = payload[sent[id]..sent[id] + n].to_vec();
+                send(&mut client, &[Frame::Data(*id, data)]).await;
+                *sent.get_mut(id).unwrap() += n;
+                *credit.get_mut(id).unwrap() -= n;
+                if sent[id] == payload.len() {
+                    send(&mut client, &[Frame::Close(*id)]).await;
+                }
+            }
+        }
+
+        match receive(&mut client).await.unwrap() {
+            Frame::Open(id) => opened.push(id),
+            Frame::Data(id, data) => {
+                echoed.entry(id).or_default().extend_from_slice(&data);
+                // accept more echo at once
+                send(&mut client, &[Frame::WindowUpdate(id, data.len() as u32)]).await;
+            }
+            Frame::WindowUpdate(id, increment) => {
+                *credit.get_mut(&id).unwrap() += increment as usize
+            }
+            Frame::Close(id) => closed.push(id),
+        }
+    }
+
+    // every stream accepted and echoed as sent
+    assert_eq!(opened, vec![1, 3, 7]);
+    assert_eq!(echoed, payloads);
+
+    // nothing more once the client is done
+    client.shutdown().await.unwrap();
+    assert_eq!(receive(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn stalled_stream_does_not_hold_up_others() {
+    let address = start_server(&["--protocol", "mux", "--mux-window", "16"])
+        .await
+        .0;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    send(&mut client, &[Frame::Open(1), Frame::Open(2)]).await;
+    assert_eq!(receive(&mut client).await, Some(Frame::Open(1)));
+    assert_eq!(receive(&mut client).await, Some(Frame::Open(2)));
+
+    // a whole window is echoed and granted back
+    send(&mut client, &[Frame::Data(1, vec![b'a'; 16])]).await;
+    assert_eq!(
+        receive(&mut client).await,
+        Some(Frame::Data(1, vec![b'a'; 16]))
+    );
+    assert_eq!(receive(&mut client).await, Some(Frame::WindowUpdate(1, 16)));
+
+    // the next one waits for us to accept more echo
+    send(&mut client, &[Frame::Data(1, vec![b'b'; 16])]).await;
+
+    // while stream 2 goes on
+    send(&mut client, &[Frame::Data(2, b"other".to_vec())]).await;
+    assert_eq!(
+        receive(&mut client).await,
+        Some(Frame::Data(2, b"other".to_vec()))
+    );
+    assert_eq!(receive(&mut client).await, Some(Frame::WindowUpdate(2, 5)));
+
+    // half a window releases half of the waiting echo
+    send(&mut client, &[Frame::WindowUpdate(1, 8)]).await;
+    assert_eq!(
+        receive(&mut client).await,
+        Some(Frame::Data(1, vec![b'b'; 8]))
+    );
+    assert_eq!(receive(&mut client).await, Some(Frame::WindowUpdate(1, 8)));
+
+    // closing waits for the rest of the echo
+    send(&mut client, &[Frame::Close(1), Frame::WindowUpdate(1, 8)]).await;
+    assert_eq!(
+        receive(&mut client).await,
+        Some(Frame::Data(1, vec![b'b'; 8]))
+    );
+    assert_eq!(receive(&mut client).await, Some(Frame::WindowUpdate(1, 8)));
+    assert_eq!(receive(&mut client).await, Some(Frame::Close(1)));
+}
+
+#[tokio::test]
+async fn drops_clients_breaking_the_protocol() {
+    let address = start_server(&["--protocol", "mux", "--mux-window", "16"])
+        .await
+        .0;
+
+    // each of these ends the connection
+    let violations = [
+        vec![Frame::Data(1, b"not open".to_vec())],
+        vec![Frame::Open(1), Frame::Open(1)],
+        vec![Frame::Open(1), Frame::Data(1, vec![0; 17])],
+        vec![
+            Frame::Open(1),
+            Frame::Data(1, vec![0; 16]),
+            Frame::Data(1, vec![0; 16]),
+            Frame::Data(1, vec![0; 1]),
+        ],
+        vec![Frame::Open(1), Frame::Close(1), Frame::Data(1, vec![0])],
+    ];
+    for frames in &violations {
+        let mut client = TcpStream::connect(address).await.unwrap();
+        send(&mut client, frames).await;
+        // whatever was answered before, then the end
+        while receive(&mut client).await.is_some() {}
+    }
+
+    // unknown frame types too
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client
+        .write_all(&[9, 0, 0, 0, 1, 0, 0, 0, 0])
+        .await
+        .unwrap();
+    assert_eq!(receive(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn client_not_reading_its_echo_is_not_buffered_for() {
+    // a small pipe, filled up as the client never reads
+    let (mut client, mut connection) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        serve_connection(&mut connection, address, 1024, 256, true).await
+    });
+
+    // granting echo and sending as if all of it was read and granted back
+    let mut open = Vec::new();
+    Frame::Open(1).encode(&mut open);
+    client.write_all(&open).await.unwrap();
+    let mut frames = Vec::new();
+    Frame::Data(1, vec![0; 1024]).encode(&mut frames);
+    Frame::WindowUpdate(1, 1024).encode(&mut frames);
+    let mut sent = 0;
+    while sent < 64 * 1024 * 1024 && client.write_all(&frames).await.is_ok() {
+        sent += 1024;
+    }
+
+    // no credit once the pipe is full, so the client overruns its window
+    let error = server.await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(sent < 1024 * 1024);
+}
+
+#[tokio::test]
+async fn limits_open_streams() {
+    let (mut client, mut connection) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move {
+        let address = "127.0.0.1:1".parse().unwrap();
+        serve_connection(&mut connection, address, 16, 2, true).await
+    });
+
+    // closed streams make room for others, once the close was answered
+    let mut frames = Vec::new();
+    for frame in [Frame::Open(1), Frame::Open(2), Frame::Close(1)] {
+        frame.encode(&mut frames);
+    }
+    client.write_all(&frames).await.unwrap();
+    let mut answers = vec![0; frames.len()];
+    client.read_exact(&mut answers).await.unwrap();
+    assert_eq!(answers, frames);
+
+    // but never more than the limit
+    let mut frames = Vec::new();
+    for frame in [Frame::Open(3), Frame::Open(4)] {
+        frame.encode(&mut frames);
+    }
+    client.write_all(&frames).await.unwrap();
+    let error = server.await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::QuotaExceeded);
+    // dropped as soon as the limit is broken
+    let mut answers = Vec::new();
+    client.read_to_end(&mut answers).await.unwrap();
+    assert!(answers.is_empty());
+
+    // at least one stream
+    assert!(Config::try_parse_from(["server", "--mux-max-streams", "0"]).is_err());
+}
+
+#[test]
+fn frames_round_trip() {
+    let frames = [
+        Frame::Open(1),
+        Frame::Data(u32::MAX, b"payload".to_vec()),
+        Frame::Close(2),
+        Frame::WindowUpdate(3, 65536),
+    ];
+    for frame in &frames {
+        let mut bytes = Vec::new();
+        frame.encode(&mut bytes);
+        // incomplete until the last byte
+        for end in 0..bytes.len() {
+            assert_eq!(Frame::parse(&bytes[..end], 1024).unwrap(), None);
+        }
+        assert_eq!(
+            Frame::parse(&bytes, 1024).unwrap(),
+            Some((frame.clone(), bytes.len()))
+        );
+    }
+}
+
+#[test]
+fn window_must_not_be_empty() {
+    let config = Config::parse_from(["server", "--protocol", "mux", "--mux-window", "0"]);
+    assert!(Server::new(config).is_err());
+}
+
+#[test]
+fn gives_up_on_writers_taking_nothing() {
+    // on a thread of its own, a server writing forever never yields to a timer
+    let (finished, result) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        // opening a stream is answered, but the answer never goes anywhere
+        let mut frames = Vec::new();
+        Frame::Open(1).encode(&mut frames);
+        let mut connection = tokio::io::join(&frames[..], Stuck);
+
+        let address = "127.0.0.1:1".parse().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let _ = finished.send(runtime.block_on(serve_connection(
+            &mut connection,
+            address,
+            16,
+            2,
+            true,
+        )));
+    });
+
+    let result = result
+        .recv_timeout(Duration::from_secs(5))
+        .expect("kept writing to a writer taking nothing");
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WriteZero);
+}
@@ -0,0 +1,218 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for addresses, file paths and timing
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// upper-cases each chunk in place
+const UPPER: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.and
+              (i32.ge_u (i32.load8_u (local.get $i)) (i32.const 97))
+              (i32.le_u (i32.load8_u (local.get $i)) (i32.const 122)))
+          (then (i32.store8 (local.get $i) (i32.sub (i32.load8_u (local.get $i)) (i32.const 32)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
+"#;
+
+/// answers nothing until the client is done, then the count of bytes it sent
+const COUNT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $total (mut i32) (i32.const 0))
+  (func (export "alloc") (param i32) (result i32) (i32.const 16))
+  (func (export "transform") (param i32) (param $len i32) (result i64)
+    (global.set $total (i32.add (global.get $total) (local.get $len)))
+    (i64.const 0))
+  (func (export "finish") (result i64)
+    (i32.store (i32.const 0) (global.get $total))
+    (i64.const 4)))
+"#;
+
+/// never returns
+const SPIN: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const 0)))
+"#;
+
+/// never returns for chunks starting with `s`, echoes the others
+const SPIN_ON_S: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 115))
+      (then (loop $forever (br $forever))))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
+"#;
+
+/// asks for four pages of memory up front
+const LARGE: &str = r#"
+(module
+  (memory (export "memory") 4)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param i32 i32) (result i64) (i64.const 0)))
+"#;
+
+/// write a plugin in text format to a file unique to this test
+fn plugin_file(name: &str, source: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("echo-plugin-{}-{}.wat", name, std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+/// send `payload`, shut down and return everything answered
+async fn session(address: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(payload).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut answer = Vec::new();
+    // the server may reset the connection
+    let _ = client.read_to_end(&mut answer).await;
+    answer
+}
+
+#[tokio::test]
+async fn answers_with_what_the_plugin_returns() {
+    let path = plugin_file("upper", UPPER);
+    let (address, _) = start_server(&["--wasm-plugin", path.to_str().unwrap()]).await;
+
+    assert_eq!(session(address, b"hello, World!").await, b"HELLO, WORLD!");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn keeps_state_per_connection() {
+    let path = plugin_file("count", COUNT);
+    let (address, _) = start_server(&[
+        "--wasm-plugin",
+        path.to_str().unwrap(),
+        "--buffer-size",
+        "7",
+    ])
+    .await;
+
+    // chunks add up within a connection, never across
+    for payload in [&[1u8; 100][..], &[2u8; 3][..]] {
+        let answer = session(address, payload).await;
+        assert_eq!(answer, (payload.len() as u32).to_le_bytes());
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn stops_plugins_out_of_fuel() {
+    let path = plugin_file("spin", SPIN);
+    let (address, server) = start_server(&[
+        "--wasm-plugin",
+        path.to_str().unwrap(),
+        "--wasm-fuel",
+        "1000000",
+    ])
+    .await;
+
+    // the connection ends without an answer
+    let answer = tokio::time::timeout(Duration::from_secs(10), session(address, b"hello"))
+        .await
+        .unwrap();
+    assert!(answer.is_empty());
+
+    // and counts as failed
+    for _ in 0..100 {
+        match server.stats.get("127.0.0.1".parse().unwrap()) {
+            Some(stats) if stats.errors == 1 => {
+                std::fs::remove_file(path).unwrap();
+                return;
+            }
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("session was not recorded as failed");
+}
+
+#[tokio::test]
+async fn busy_plugins_do_not_hold_up_other_connections() {
+    let path = plugin_file("spin-on-s", SPIN_ON_S);
+    // seconds of fuel, on the single thread of the test runtime
+    let (address, _) = start_server(&[
+        "--wasm-plugin",
+        path.to_str().unwrap(),
+        "--wasm-fuel",
+        "3000000000",
+    ])
+    .await;
+
+    // one connection keeps its plugin busy
+    let started = Instant::now();
+    let mut busy = TcpStream::connect(address).await.unwrap();
+    busy.write_all(b"spin").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // another is still answered right away, the runtime thread was never blocked
+    assert_eq!(session(address, b"hello").await, b"hello");
+    assert!(started.elapsed() < Duration::from_secs(1));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn refuses_plugins_beyond_the_memory_limit() {
+    let path = plugin_file("large", LARGE);
+    let args = |limit: &'static str| {
+        Config::parse_from([
+            "server",
+            "--wasm-plugin",
+            path.to_str().unwrap(),
+            "--wasm-memory-limit",
+            limit,
+        ])
+    };
+
+    // four pages of 64 KiB
+    assert!(Server::new(args("131072")).is_err());
+    assert!(Server::new(args("262144")).is_ok());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn plugins_only_answer_raw_chunks() {
+    let path = plugin_file("protocols", UPPER);
+    for args in [["--protocol", "line"], ["--copy-mode", "splice"]] {
+        let config = Config::parse_from(
+            ["server", "--wasm-plugin", path.to_str().unwrap()]
+                .iter()
+                .chain(args.iter())
+                .copied(),
+        );
+        assert!(Server::new(config).is_err());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,313 @@
+// use tokio for async runtime
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// use schnorrkel to check signatures
+use schnorrkel::{PublicKey, Signature};
+
+// use serde_json to play the node
+use serde_json::{json, Value};
+
+// types for addresses, sharing and timing
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use substrate_course_task_2::poe::{
+    compact, create_claim_call, ss58, CallIndex, NodeSubmitter, Submission, Submitter, ALICE_SEED,
+};
+use substrate_course_task_2::transcript::transcript_hash;
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server_on;
+
+/// public key of `//Alice`
+const ALICE: &str = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+/// genesis hash announced by the fake node
+const GENESIS: [u8; 32] = [7; 32];
+
+/// submitter remembering claims instead of sending them anywhere
+struct Mock(mpsc::UnboundedSender<[u8; 32]>);
+
+impl Submitter for Mock {
+    fn submit(&self, claim: [u8; 32]) -> Submission<'_> {
+        self.0.send(claim).unwrap();
+        Box::pin(async { Ok("0xmock".to_string()) })
+    }
+}
+
+/// send `payload` in one session, returning what came back
+async fn session(address: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(payload).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+    echoed
+}
+
+/// JSON-RPC node answering just enough to submit, handing over submitted extrinsics
+async fn fake_node() -> (String, mpsc::UnboundedReceiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (submitted, extrinsics) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = BufReader::new(socket);
+            // headers, remembering the body length
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                socket.read_line(&mut line).await.unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            socket.read_exact(&mut body).await.unwrap();
+            let request: Value = serde_json::from_slice(&body).unwrap();
+
+            // answer like a dev node
+            let result = match request["method"].as_str().unwrap() {
+                "chain_getBlockHash" => json!(format!("0x{}", hex::encode(GENESIS))),
+                "state_getRuntimeVersion" => json!({ "specVersion": 100, "transactionVersion": 1 }),
+                "system_accountNextIndex" => {
+                    assert_eq!(request["params"][0], ss58(&alice()));
+                    json!(5)
+                }
+                "author_submitExtrinsic" => {
+                    let extrinsic = request["params"][0].as_str().unwrap();
+                    submitted
+                        .send(hex::decode(&extrinsic[2..]).unwrap())
+                        .unwrap();
+                    json!("0xfeed")
+                }
+                method => panic!("unexpected method {}", method),
+            };
+            let body =
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.unwrap();
+        }
+    });
+
+    (url, extrinsics)
+}
+
+/// node answering every request with `response`, or never when there is none
+async fn raw_node(response: Option<&'static [u8]>) -> NodeSubmitter {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        let mut hung = Vec::new();
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            match response {
+                Some(response) => {
+                    let mut request = [0; 1024];
+                    let _ = socket.read(&mut request).await;
+                    socket.write_all(response).await.unwrap();
+                }
+                // keep the connection open without a word
+                None => hung.push(socket),
+            }
+        }
+    });
+
+    let seed = hex::decode(ALICE_SEED).unwrap();
+    NodeSubmitter::new(
+        &url,
+        &seed,
+        CallIndex { pallet: 8, call: 0 },
+        Duration::from_millis(200),
+    )
+    .unwrap()
+}
+
+/// public key of `//Alice` as bytes
+fn alice() -> [u8; 32] {
+    let mut public = [0; 32];
+    hex::decode_to_slice(ALICE, &mut public).unwrap();
+    public
+}
+
+#[tokio::test]
+async fn anchors_the_transcript_of_every_session() {
+    let (claims, mut anchored) = mpsc::unbounded_channel();
+    let address = start_server_on(
+        "127.0.0.1",
+        &["--protocol", "line"],
+        Some(Arc::new(Mock(claims))),
+    )
+    .await
+    .0;
+
+    // the transcript covers both directions
+    let echoed = session(address, b"MODE upper\nhello\n").await;
+    assert_eq!(echoed, b"OK mode upper\nHELLO\n");
+    let claim = tokio::time::timeout(Duration::from_secs(5), anchored.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claim, transcript_hash(b"MODE upper\nhello\n", &echoed));
+
+    // one claim per session
+    session(address, b"hello\n").await;
+    let claim = anchored.recv().await.unwrap();
+    assert_eq!(claim, transcript_hash(b"hello\n", b"hello\n"));
+}
+
+#[tokio::test]
+async fn submits_signed_create_claim_extrinsics_to_the_node() {
+    let (url, mut extrinsics) = fake_node().await;
+    let address = start_server_on("127.0.0.1", &["--poe-node", &url], None)
+        .await
+        .0;
+
+    // one session
+    session(address, b"anchor me").await;
+    let extrinsic = tokio::time::timeout(Duration::from_secs(5), extrinsics.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let claim = transcript_hash(b"anchor me", b"anchor me");
+
+    // length prefix, then a signed version 4 extrinsic from Alice
+    let mut prefix = Vec::new();
+    compact(extrinsic.len() as u64 - 2, &mut prefix);
+    assert_eq!(extrinsic[..2], prefix[..]);
+    let body = &extrinsic[2..];
+    assert_eq!(body[..2], [0x84, 0x00]);
+    assert_eq!(body[2..34], alice());
+    assert_eq!(body[34], 0x01);
+    let signature = Signature::from_bytes(&body[35..99]).unwrap();
+    // immortal era, nonce 5, no tip
+    let extra = &body[99..102];
+    assert_eq!(extra, [0x00, 5 << 2, 0x00]);
+    // Poe::create_claim(claim)
+    let call = &body[102..];
+    assert_eq!(
+        call,
+        create_claim_call(CallIndex { pallet: 8, call: 0 }, &claim)
+    );
+
+    // signed over call, extra, versions and genesis hash
+    let payload = [
+        call,
+        extra,
+        &100u32.to_le_bytes(),
+        &1u32.to_le_bytes(),
+        &GENESIS,
+        &GENESIS,
+    ]
+    .concat();
+    PublicKey::from_bytes(&alice())
+        .unwrap()
+        .verify_simple(b"substrate", &payload, &signature)
+        .unwrap();
+}
+
+#[tokio::test]
+async fn gives_up_on_hung_nodes() {
+    let submitter = raw_node(None).await;
+
+    // every submission fails in time, none waits for an earlier one for good
+    for _ in 0..2 {
+        let result = tokio::time::timeout(Duration::from_secs(2), submitter.submit([1; 32]))
+            .await
+            .expect("submission waited for the node");
+        assert!(format!("{:#}", result.unwrap_err()).contains("timed out"));
+    }
+}
+
+#[tokio::test]
+async fn refuses_chunks_larger_than_the_response() {
+    let submitter = raw_node(Some(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n{}\r\n0\r\n\r\n",
+    ))
+    .await;
+
+    // huge chunk sizes fail instead of overflowing
+    let error = submitter.submit([1; 32]).await.unwrap_err();
+    assert!(format!("{:#}", error).contains("chunk"));
+}
+
+#[test]
+fn signs_as_alice_by_default() {
+    let seed = hex::decode(ALICE_SEED).unwrap();
+    let submitter = NodeSubmitter::new(
+        "http://127.0.0.1:9933",
+        &seed,
+        CallIndex { pallet: 8, call: 0 },
+        Duration::from_secs(10),
+    )
+    .unwrap();
+    assert_eq!(
+        submitter.account(),
+        "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+    );
+    assert!(NodeSubmitter::new(
+        "https://node",
+        &seed,
+        CallIndex { pallet: 8, call: 0 },
+        Duration::from_secs(10)
+    )
+    .is_err());
+}
+
+#[test]
+fn encodes_compact_integers_and_calls() {
+    // every compact mode
+    for (value, encoded) in [
+        (0, &[0x00][..]),
+        (63, &[0xfc]),
+        (64, &[0x01, 0x01]),
+        (16383, &[0xfd, 0xff]),
+        (16384, &[0x02, 0x00, 0x01, 0x00]),
+        (1 << 30, &[0x03, 0x00, 0x00, 0x00, 0x40]),
+        (
+            u64::MAX,
+            &[0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        ),
+    ] {
+        let mut out = Vec::new();
+        compact(value, &mut out);
+        assert_eq!(out, encoded, "{}", value);
+    }
+
+    // pallet, call, then the proof as a Vec<u8>
+    assert_eq!(
+        create_claim_call(CallIndex { pallet: 8, call: 0 }, b"abc"),
+        [8, 0, 3 << 2, b'a', b'b', b'c']
+    );
+}
+
+#[test]
+fn splice_cannot_be_anchored() {
+    let config = Config::parse_from([
+        "server",
+        "--copy-mode",
+        "splice",
+        "--poe-node",
+        "http://127.0.0.1:9933",
+    ]);
+    assert!(Server::new(config).is_err());
+}
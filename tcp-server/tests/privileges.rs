@@ -0,0 +1,208 @@
+// use std networking for a blocking client
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for C strings, running the binary and for file paths
+use std::ffi::CString;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+use substrate_course_task_2::privileges::Needs;
+use substrate_course_task_2::Config;
+
+// starting the server binary, shared by the tests
+mod common;
+use common::start_binary;
+
+/// whether the tests run as root, the only way to test dropping privileges
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// a directory of its own for one test, owned by `nobody`
+fn jail(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("echo-jail-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir(&directory).unwrap();
+    std::os::unix::fs::chown(&directory, Some(65534), Some(65534)).unwrap();
+    directory
+}
+
+/// ids of a running process, as listed in /proc
+fn ids(child: &Child, field: &str) -> String {
+    let status = fs::read_to_string(format!("/proc/{}/status", child.id())).unwrap();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field))
+        .unwrap()
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn refuses_to_run_as_root_without_dropping_privileges() {
+    if !is_root() {
+        return;
+    }
+
+    // nothing is bound, the server exits right away
+    let output = Command::new(env!("CARGO_BIN_EXE_substrate-course-task-2"))
+        .args(["--quiet", "--listen", "127.0.0.1:0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--allow-root"));
+
+    // unless asked to
+    let config = Config::parse_from(["server", "--allow-root"]);
+    assert!(config.privileges.check().is_ok());
+    let config = Config::parse_from(["server", "--user", "nobody"]);
+    assert!(config.privileges.check().is_ok());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn echoes_after_dropping_privileges() {
+    if !is_root() {
+        return;
+    }
+
+    // everything at once, statistics are loaded from and saved to the jail
+    let directory = jail("echo");
+    let (mut child, address) = start_binary(
+        &directory,
+        &[
+            "--user",
+            "nobody",
+            "--chroot",
+            directory.to_str().unwrap(),
+            "--seccomp",
+            "--stats-file",
+            "stats.json",
+        ],
+    );
+
+    // no way back to root
+    assert!(ids(&child, "Uid:")
+        .split_whitespace()
+        .all(|id| id == "65534"));
+    assert!(ids(&child, "Gid:")
+        .split_whitespace()
+        .all(|id| id == "65534"));
+    assert_eq!(ids(&child, "Groups:"), "65534");
+    assert_eq!(ids(&child, "Seccomp:"), "2");
+
+    // still echoes
+    let mut client = TcpStream::connect(&address).unwrap();
+    client.write_all(b"hello").unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut echoed = String::new();
+    client.read_to_string(&mut echoed).unwrap();
+    assert_eq!(echoed, "hello");
+
+    // and shuts down cleanly, saving statistics
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    assert!(child.wait().unwrap().success());
+    assert!(directory.join("stats.json").exists());
+
+    let _ = fs::remove_dir_all(&directory);
+}
+
+/// set in a copy of this test binary to make the forbidden system call named
+const FORBIDDEN_CALL: &str = "ECHO_FORBIDDEN_CALL";
+
+#[cfg(target_os = "linux")]
+#[test]
+fn kills_on_forbidden_system_calls() {
+    // in the copy: filter as the server would, then misbehave
+    if let Ok(call) = std::env::var(FORBIDDEN_CALL) {
+        let config = Config::parse_from(["server", "--seccomp", "--allow-root"]);
+        // resolving names only needs to read files
+        let resolving = call.ends_with("_while_resolving");
+        let needs = Needs {
+            connect: resolving,
+            ..Needs::default()
+        };
+        config.privileges.apply(needs).unwrap();
+        let path = CString::new("/etc/hosts").unwrap();
+        unsafe {
+            match call.as_str() {
+                "socket" => libc::socket(libc::AF_NETLINK, libc::SOCK_RAW, 0),
+                "fork" => libc::fork(),
+                // files, and connecting out, only when the server needs them
+                "open" => libc::open(path.as_ptr(), libc::O_RDONLY),
+                "read_while_resolving" => libc::open(path.as_ptr(), libc::O_RDONLY),
+                "write_while_resolving" => libc::open(path.as_ptr(), libc::O_WRONLY),
+                "connect" => {
+                    let socket = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+                    let address: libc::sockaddr_in = std::mem::zeroed();
+                    libc::connect(
+                        socket,
+                        &address as *const libc::sockaddr_in as *const libc::sockaddr,
+                        std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    )
+                }
+                "io_uring" => {
+                    let mut params = [0u8; 120];
+                    libc::syscall(libc::SYS_io_uring_setup, 1, params.as_mut_ptr()) as i32
+                }
+                _ => unreachable!(),
+            };
+        }
+        return;
+    }
+
+    // a copy of this test binary running only this test
+    let run = |call| {
+        Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "kills_on_forbidden_system_calls",
+                "--test-threads",
+                "1",
+            ])
+            .env(FORBIDDEN_CALL, call)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+    };
+    for call in [
+        "socket",
+        "fork",
+        "io_uring",
+        "open",
+        "connect",
+        "write_while_resolving",
+    ] {
+        let status = run(call);
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(libc::SIGSYS),
+            "{} was not killed",
+            call
+        );
+    }
+    // what is needed goes through
+    assert!(run("read_while_resolving").success());
+}
+
+#[test]
+fn fails_on_unknown_users() {
+    if !is_root() {
+        return;
+    }
+
+    // looked up after binding, before serving
+    let output = Command::new(env!("CARGO_BIN_EXE_substrate-course-task-2"))
+        .args(["--quiet", "--listen", "127.0.0.1:0"])
+        .args(["--user", "no-such-user-here"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No such user"));
+}
@@ -0,0 +1,156 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+// use proptest to generate sessions
+use proptest::prelude::*;
+
+// use socket2 to reset connections
+use socket2::SockRef;
+
+// types for addresses, lazy statics and timing
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// runtime shared by every server and client of this file
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().unwrap())
+}
+
+/// a whole session: send `payload` in chunks of the given sizes, pausing after each
+/// by the given delays, then shut down and return everything echoed
+async fn session(
+    address: SocketAddr,
+    payload: Vec<u8>,
+    chunks: Vec<usize>,
+    delays: Vec<u64>,
+) -> Vec<u8> {
+    let client = TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = client.into_split();
+
+    // write while reading, so neither side blocks on full buffers
+    let send = tokio::spawn(async move {
+        let mut rest = &payload[..];
+        for (i, size) in chunks.iter().cycle().enumerate() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+            writer.write_all(chunk).await.unwrap();
+            writer.flush().await.unwrap();
+            rest = tail;
+            // let the server see a separate segment
+            if let Some(&delay) = delays.get(i % delays.len().max(1)) {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+        }
+        writer.shutdown().await.unwrap();
+    });
+    let mut echoed = Vec::new();
+    reader.read_to_end(&mut echoed).await.unwrap();
+    send.await.unwrap();
+
+    echoed
+}
+
+/// copy modes to check, splice only exists on Linux
+fn copy_modes() -> Vec<&'static str> {
+    let mut modes = vec!["loop", "buffered"];
+    if cfg!(target_os = "linux") {
+        modes.push("splice");
+    }
+    modes
+}
+
+#[test]
+fn echoes_randomly_chunked_payloads() {
+    for mode in copy_modes() {
+        let address = runtime()
+            .block_on(start_server(&[
+                "--copy-mode",
+                mode,
+                "--buffer-size",
+                "4096",
+            ]))
+            .0;
+
+        proptest!(ProptestConfig::with_cases(32), |(
+            payload in prop::collection::vec(any::<u8>(), 0..64 * 1024),
+            chunks in prop::collection::vec(1..8192usize, 1..16),
+        )| {
+            let echoed = runtime().block_on(session(address, payload.clone(), chunks, vec![]));
+            prop_assert_eq!(echoed, payload, "copy mode {}", mode);
+        });
+    }
+}
+
+#[test]
+fn echoes_despite_client_delays() {
+    for mode in copy_modes() {
+        let address = runtime().block_on(start_server(&["--copy-mode", mode])).0;
+
+        proptest!(ProptestConfig::with_cases(16), |(
+            payload in prop::collection::vec(any::<u8>(), 0..4096),
+            chunks in prop::collection::vec(1..512usize, 1..8),
+            delays in prop::collection::vec(0..5u64, 1..8),
+        )| {
+            let echoed = runtime().block_on(session(address, payload.clone(), chunks, delays));
+            prop_assert_eq!(echoed, payload, "copy mode {}", mode);
+        });
+    }
+}
+
+#[test]
+fn echoes_random_lines_in_line_mode() {
+    let address = runtime().block_on(start_server(&["--protocol", "line"])).0;
+
+    // lines starting with a digit are never commands
+    proptest!(ProptestConfig::with_cases(32), |(
+        lines in prop::collection::vec("[0-9][a-z0-9 ]{0,40}(\n|\r\n)", 0..32),
+        chunks in prop::collection::vec(1..64usize, 1..8),
+    )| {
+        let payload = lines.concat().into_bytes();
+        let echoed = runtime().block_on(session(address, payload.clone(), chunks, vec![]));
+        prop_assert_eq!(echoed, payload);
+    });
+}
+
+#[test]
+fn survives_random_disconnects() {
+    for mode in copy_modes() {
+        let address = runtime().block_on(start_server(&["--copy-mode", mode])).0;
+
+        proptest!(ProptestConfig::with_cases(16), |(
+            payload in prop::collection::vec(any::<u8>(), 1..64 * 1024),
+            cut in any::<prop::sample::Index>(),
+            read_some in any::<bool>(),
+            reset in any::<bool>(),
+        )| {
+            let echoed = runtime().block_on(async {
+                // send part of a payload and go away, politely or not
+                let mut client = TcpStream::connect(address).await.unwrap();
+                let sent = &payload[..cut.index(payload.len())];
+                client.write_all(sent).await.unwrap();
+                if read_some && !sent.is_empty() {
+                    let mut byte = [0; 1];
+                    client.read_exact(&mut byte).await.unwrap();
+                }
+                if reset {
+                    SockRef::from(&client).set_linger(Some(Duration::ZERO)).unwrap();
+                }
+                drop(client);
+
+                // the server keeps serving others
+                session(address, payload.clone(), vec![4096], vec![]).await
+            });
+            prop_assert_eq!(echoed, payload, "copy mode {}", mode);
+        });
+    }
+}
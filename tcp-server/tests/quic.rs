@@ -0,0 +1,359 @@
+// use quinn for the client side
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::rustls::pki_types::PrivatePkcs8KeyDer;
+use quinn::rustls::{self, RootCertStore};
+use quinn::{ClientConfig, Endpoint};
+
+// use tokio to hand over claims
+use tokio::sync::mpsc;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for conversions, addresses, sharing and timing
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+// use sha2 for certificate fingerprints
+use sha2::{Digest, Sha256};
+
+use substrate_course_task_2::audit::{CloseReason, Entry, Transport};
+use substrate_course_task_2::poe::{Submission, Submitter};
+use substrate_course_task_2::quic::{self, ConnectionSummary, Identity, ALPN};
+use substrate_course_task_2::transcript::transcript_hash;
+use substrate_course_task_2::{Config, Server};
+
+/// loopback address the test clients come from
+const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// submitter remembering claims instead of sending them anywhere
+struct Mock(mpsc::UnboundedSender<[u8; 32]>);
+
+impl Submitter for Mock {
+    fn submit(&self, claim: [u8; 32]) -> Submission<'_> {
+        self.0.send(claim).unwrap();
+        Box::pin(async { Ok("0xmock".to_string()) })
+    }
+}
+
+/// a QUIC endpoint with a fresh self-signed certificate, and that certificate
+fn server_endpoint() -> (Endpoint, Identity) {
+    let identity = Identity::self_signed().unwrap();
+    let trusted = Identity {
+        certificates: identity.certificates.clone(),
+        key: identity.key.clone_key(),
+    };
+    let endpoint = quic::endpoint("127.0.0.1:0".parse().unwrap(), identity, &[]).unwrap();
+    (endpoint, trusted)
+}
+
+/// a client trusting `identity`, offering the given ALPN protocols
+fn client(identity: &Identity, alpn: &[&[u8]]) -> Endpoint {
+    client_presenting(identity, alpn, None)
+}
+
+/// a client trusting `identity`, offering the given ALPN protocols and presenting
+/// `certificate`, if any
+fn client_presenting(
+    identity: &Identity,
+    alpn: &[&[u8]],
+    certificate: Option<Identity>,
+) -> Endpoint {
+    let mut roots = RootCertStore::empty();
+    roots.add(identity.certificates[0].clone()).unwrap();
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let mut crypto = match certificate {
+        Some(certificate) => builder
+            .with_client_auth_cert(certificate.certificates, certificate.key)
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    crypto.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+    let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(crypto).unwrap(),
+    )));
+    endpoint
+}
+
+/// send `payload` on a new stream and return the echo
+async fn stream(connection: &quinn::Connection, payload: Vec<u8>) -> Vec<u8> {
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    let write = tokio::spawn(async move {
+        send.write_all(&payload).await.unwrap();
+        send.finish().unwrap();
+    });
+    let echoed = recv.read_to_end(1024 * 1024).await.unwrap();
+    write.await.unwrap();
+    echoed
+}
+
+/// open a few streams at once, each echoed on its own, then close the connection
+async fn session(address: SocketAddr, identity: &Identity) -> Vec<Vec<u8>> {
+    let client = client(identity, &[ALPN]);
+    let connection = client.connect(address, "localhost").unwrap().await.unwrap();
+
+    let payloads = [b"hello".to_vec(), vec![7; 200_000], b"world!".to_vec()];
+    let echoes = streams(&connection, &payloads).await;
+
+    connection.close(0u32.into(), b"done");
+    client.wait_idle().await;
+    echoes
+}
+
+/// echo each payload on its own stream, all at once
+async fn streams(connection: &quinn::Connection, payloads: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut tasks = Vec::new();
+    for payload in payloads {
+        let connection = connection.clone();
+        let payload = payload.clone();
+        tasks.push(tokio::spawn(
+            async move { stream(&connection, payload).await },
+        ));
+    }
+    let mut echoes = Vec::new();
+    for task in tasks {
+        echoes.push(task.await.unwrap());
+    }
+    echoes
+}
+
+#[tokio::test]
+async fn echoes_every_bidirectional_stream() {
+    let (endpoint, identity) = server_endpoint();
+    let address = endpoint.local_addr().unwrap();
+    let server = Arc::new(Server::new(Config::parse_from(["server", "--quiet"])).unwrap());
+
+    // serve one connection directly
+    let handler = tokio::spawn(async move {
+        let connection = endpoint.accept().await.unwrap().await.unwrap();
+        quic::handle_client(connection, server).await.unwrap()
+    });
+
+    let echoes = session(address, &identity).await;
+    assert_eq!(echoes[0], b"hello");
+    assert_eq!(echoes[1], vec![7; 200_000]);
+    assert_eq!(echoes[2], b"world!");
+
+    // three streams, counted together
+    assert_eq!(
+        handler.await.unwrap(),
+        ConnectionSummary {
+            streams: 3,
+            failed_streams: 0,
+            echoed_bytes: 200_011,
+        }
+    );
+}
+
+#[tokio::test]
+async fn records_connections_like_tcp_sessions() {
+    let (endpoint, identity) = server_endpoint();
+    let address = endpoint.local_addr().unwrap();
+    let server = Arc::new(Server::new(Config::parse_from(["server", "--quiet"])).unwrap());
+    tokio::spawn(quic::serve(endpoint, server.clone()));
+
+    session(address, &identity).await;
+    session(address, &identity).await;
+
+    // one session per connection, with the bytes of all its streams
+    for _ in 0..100 {
+        match server.stats.get(LOOPBACK) {
+            Some(stats) if stats.sessions == 2 => {
+                assert_eq!(stats.bytes, 2 * 200_011);
+                assert_eq!(stats.errors, 0);
+                return;
+            }
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("connections were not recorded");
+}
+
+#[tokio::test]
+async fn speaks_the_configured_protocol_on_each_stream() {
+    let (endpoint, identity) = server_endpoint();
+    let address = endpoint.local_addr().unwrap();
+    let config = Config::parse_from(["server", "--quiet", "--protocol", "line"]);
+    tokio::spawn(quic::serve(
+        endpoint,
+        Arc::new(Server::new(config).unwrap()),
+    ));
+
+    // sessions are per stream
+    let client = client(&identity, &[ALPN]);
+    let connection = client.connect(address, "localhost").unwrap().await.unwrap();
+    let upper = stream(&connection, b"MODE upper\nhello\n".to_vec()).await;
+    let plain = stream(&connection, b"hello\n".to_vec()).await;
+    assert_eq!(upper, b"OK mode upper\nHELLO\n");
+    assert_eq!(plain, b"hello\n");
+}
+
+#[tokio::test]
+async fn anchors_the_transcript_of_every_stream() {
+    let (endpoint, identity) = server_endpoint();
+    let address = endpoint.local_addr().unwrap();
+    let (claims, mut anchored) = mpsc::unbounded_channel();
+    let mut server = Server::new(Config::parse_from(["server", "--quiet"])).unwrap();
+    server.anchor = Some(Arc::new(Mock(claims)));
+    tokio::spawn(quic::serve(endpoint, Arc::new(server)));
+
+    // one claim per stream, covering both directions
+    let client = client(&identity, &[ALPN]);
+    let connection = client.connect(address, "localhost").unwrap().await.unwrap();
+    for payload in [&b"anchor me"[..], b"and me"] {
+        let echoed = stream(&connection, payload.to_vec()).await;
+        let claim = tokio::time::timeout(Duration::from_secs(5), anchored.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(claim, transcript_hash(payload, &echoed));
+    }
+}
+
+#[tokio::test]
+async fn refuses_clients_without_the_echo_protocol() {
+    let (endpoint, identity) = server_endpoint();
+    let address = endpoint.local_addr().unwrap();
+    let server = Arc::new(Server::new(Config::parse_from(["server", "--quiet"])).unwrap());
+    tokio::spawn(quic::serve(endpoint, server));
+
+    let client = client(&identity, &[b"h3"]);
+    assert!(client.connect(address, "localhost").unwrap().await.is_err());
+}
+
+#[test]
+fn generates_and_reloads_certificates() {
+    let directory = std::env::temp_dir();
+    let certificate = directory.join(format!("echo-quic-cert-{}.pem", std::process::id()));
+    let key = directory.join(format!("echo-quic-key-{}.pem", std::process::id()));
+
+    // generated on first use, then reused
+    let first = Identity::load_or_create(&certificate, &key).unwrap();
+    let second = Identity::load_or_create(&certificate, &key).unwrap();
+    assert_eq!(first.certificates, second.certificates);
+    assert_eq!(first.key.secret_der(), second.key.secret_der());
+
+    std::fs::remove_file(certificate).unwrap();
+    std::fs::remove_file(key).unwrap();
+}
+
+#[tokio::test]
+async fn audits_connections_with_all_their_streams() {
+    let (endpoint, identity) = server_endpoint();
+    let address = endpoint.local_addr().unwrap();
+    let path = std::env::temp_dir().join(format!("echo-quic-audit-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = Config::parse_from(["server", "--quiet", "--audit-log", path.to_str().unwrap()]);
+    let server = Arc::new(Server::new(config).unwrap());
+
+    // serve one connection directly
+    let handler = tokio::spawn(async move {
+        let connection = endpoint.accept().await.unwrap().await.unwrap();
+        quic::handle_client(connection, server).await.unwrap()
+    });
+    session(address, &identity).await;
+    handler.await.unwrap();
+
+    // one line for the connection, stream bytes added up
+    let log = std::fs::read_to_string(&path).unwrap();
+    let entries: Vec<Entry> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].transport, Transport::Quic);
+    assert_eq!(entries[0].reason, CloseReason::ClientEof);
+    assert_eq!(entries[0].bytes_received, 200_011);
+    assert_eq!(entries[0].bytes_sent, 200_011);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// a client certificate issued by `issuer`
+fn client_certificate(issuer: &rcgen::Issuer<'_, rcgen::KeyPair>) -> Identity {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let mut params = rcgen::CertificateParams::new(vec!["client".to_string()]).unwrap();
+    params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+    let certificate = params.signed_by(&key, issuer).unwrap();
+    Identity {
+        certificates: vec![certificate.der().clone()],
+        key: PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+    }
+}
+
+/// an authority issuing client certificates
+fn new_authority() -> rcgen::CertifiedIssuer<'static, rcgen::KeyPair> {
+    let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    rcgen::CertifiedIssuer::self_signed(params, rcgen::KeyPair::generate().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn audits_client_certificates_from_trusted_authorities() {
+    let authority = new_authority();
+    let identity = Identity::self_signed().unwrap();
+    let trusted = Identity {
+        certificates: identity.certificates.clone(),
+        key: identity.key.clone_key(),
+    };
+    let authorities = [authority.as_ref().der().clone()];
+    let endpoint = quic::endpoint("127.0.0.1:0".parse().unwrap(), identity, &authorities).unwrap();
+    let address = endpoint.local_addr().unwrap();
+    let path = std::env::temp_dir().join(format!("echo-quic-identity-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = Config::parse_from(["server", "--quiet", "--audit-log", path.to_str().unwrap()]);
+    tokio::spawn(quic::serve(
+        endpoint,
+        Arc::new(Server::new(config).unwrap()),
+    ));
+
+    // with a certificate, then without
+    let certificate = client_certificate(&authority);
+    let fingerprint = hex::encode(Sha256::digest(&certificate.certificates[0]));
+    for certificate in [Some(certificate), None] {
+        let client = client_presenting(&trusted, &[ALPN], certificate);
+        let connection = client.connect(address, "localhost").unwrap().await.unwrap();
+        assert_eq!(stream(&connection, b"hello".to_vec()).await, b"hello");
+        connection.close(0u32.into(), b"done");
+        client.wait_idle().await;
+    }
+
+    // one way or the other, unless issued by someone else
+    let stranger = client_certificate(&new_authority());
+    let client = client_presenting(&trusted, &[ALPN], Some(stranger));
+    let refused = match client.connect(address, "localhost").unwrap().await {
+        // the server may only tell once the handshake is over on its side
+        Ok(connection) => matches!(
+            connection.closed().await,
+            quinn::ConnectionError::ConnectionClosed(_)
+        ),
+        Err(_) => true,
+    };
+    assert!(refused);
+
+    for _ in 0..200 {
+        let log = std::fs::read_to_string(&path).unwrap_or_default();
+        let entries: Vec<Entry> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        if entries.len() == 2 {
+            assert_eq!(entries[0].tls_identity, Some(fingerprint));
+            assert_eq!(entries[1].tls_identity, None);
+            std::fs::remove_file(path).unwrap();
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("connections were not audited");
+}
+
+#[test]
+fn splice_cannot_serve_quic() {
+    let config = Config::parse_from(["server", "--copy-mode", "splice", "--quic", "127.0.0.1:0"]);
+    assert!(Server::new(config).is_err());
+}
@@ -0,0 +1,148 @@
+// use std networking for a blocking client
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for sharing
+use std::sync::Arc;
+
+use substrate_course_task_2::runtime::{self, available_cores};
+use substrate_course_task_2::{Config, Server};
+
+/// parse options as the binary would
+fn config(args: &[&str]) -> Config {
+    Config::parse_from(["server", "--quiet"].iter().chain(args.iter()).copied())
+}
+
+#[test]
+fn builds_each_flavor() {
+    // every flavor runs futures
+    for args in [
+        &[][..],
+        &["--worker-threads", "2"],
+        &["--runtime", "current-thread"],
+        &["--runtime", "thread-per-core"],
+    ] {
+        let runtime = config(args).runtime.build().unwrap();
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+
+    // nonsensical thread counts
+    assert!(config(&["--worker-threads", "0"]).runtime.build().is_err());
+    assert!(
+        config(&["--runtime", "current-thread", "--worker-threads", "2"])
+            .runtime
+            .build()
+            .is_err()
+    );
+}
+
+#[test]
+fn spreads_threads_over_available_cores() {
+    let available = available_cores();
+    assert!(!available.is_empty());
+
+    // one thread per core by default
+    let options = config(&["--runtime", "thread-per-core"]).runtime;
+    assert_eq!(options.cores(), available);
+
+    // more threads than cores wrap around
+    let options = config(&["--runtime", "thread-per-core", "--worker-threads", "5"]).runtime;
+    let cores = options.cores();
+    assert_eq!(cores.len(), 5);
+    assert!(cores.iter().all(|core| available.contains(core)));
+    assert_eq!(cores[0], cores[available.len() % 5]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pins_threads_to_cores() {
+    let core = available_cores()[0];
+    std::thread::spawn(move || runtime::pin_to_core(core).unwrap())
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn serves_from_every_thread_on_a_shared_port() {
+    // sockets sharing the port, as main sets up thread-per-core
+    let config = config(&["--runtime", "thread-per-core", "--reuse-port"]);
+    let listener = config.socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let address = listener.local_addr().unwrap();
+    let core = available_cores()[0];
+    let server = Arc::new(Server::new(config).unwrap());
+    runtime::serve_per_core(server, vec![listener], &[core, core, core]).unwrap();
+
+    // the kernel spreads connections over the listeners, each must echo
+    for i in 0..16 {
+        let payload = format!("hello {}", i);
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(payload.as_bytes()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut echoed = String::new();
+        client.read_to_string(&mut echoed).unwrap();
+        assert_eq!(echoed, payload);
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+#[test]
+fn io_uring_needs_the_feature() {
+    assert!(config(&["--runtime", "io-uring"]).runtime.build().is_err());
+}
+
+#[test]
+fn io_uring_only_echoes_raw_bytes() {
+    for args in [
+        &["--protocol", "line"][..],
+        &["--compression"],
+        &["--auth-key", "secret"],
+    ] {
+        let config = config(&[&["--runtime", "io-uring"][..], args].concat());
+        assert!(Server::new(config).is_err());
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[test]
+fn serves_through_io_uring() {
+    // sockets sharing the port, as main sets up io-uring
+    let config = config(&[
+        "--runtime",
+        "io-uring",
+        "--reuse-port",
+        "--buffer-size",
+        "1000",
+    ]);
+    assert_eq!(config.runtime.build().unwrap().block_on(async { 1 + 1 }), 2);
+    let listener = config.socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let address = listener.local_addr().unwrap();
+    let core = available_cores()[0];
+    let server = Arc::new(Server::new(config).unwrap());
+    runtime::serve_per_core(server.clone(), vec![listener], &[core, core]).unwrap();
+
+    // larger than the buffer, so the ring goes round
+    let payload: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    for _ in 0..8 {
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(&payload).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).unwrap();
+        assert_eq!(echoed, payload);
+    }
+
+    // accounted for like epoll sessions
+    for _ in 0..100 {
+        match server.stats.get("127.0.0.1".parse().unwrap()) {
+            Some(stats) if stats.sessions == 8 => {
+                assert_eq!(stats.bytes, 8 * 100_000);
+                return;
+            }
+            _ => std::thread::sleep(std::time::Duration::from_millis(10)),
+        }
+    }
+    panic!("sessions were not recorded");
+}
@@ -0,0 +1,196 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for addresses and timing
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use substrate_course_task_2::socks::Target;
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// loopback address the test clients come from
+const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// greet with the given methods, returning the method the server picked
+async fn greet(client: &mut TcpStream, methods: &[u8]) -> u8 {
+    let mut greeting = vec![5, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    client.write_all(&greeting).await.unwrap();
+
+    let mut choice = [0u8; 2];
+    client.read_exact(&mut choice).await.unwrap();
+    assert_eq!(choice[0], 5);
+    choice[1]
+}
+
+/// send a username and password, returning the status
+async fn log_in(client: &mut TcpStream, username: &str, password: &str) -> u8 {
+    let mut request = vec![1, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    client.write_all(&request).await.unwrap();
+
+    let mut status = [0u8; 2];
+    client.read_exact(&mut status).await.unwrap();
+    assert_eq!(status[0], 1);
+    status[1]
+}
+
+/// send a request, returning the reply code
+async fn request(client: &mut TcpStream, command: u8, target: &str) -> u8 {
+    let mut message = vec![5, command, 0];
+    target.parse::<Target>().unwrap().encode(&mut message);
+    client.write_all(&message).await.unwrap();
+
+    // header, then an IPv4 or IPv6 bound address
+    let mut header = [0u8; 4];
+    client.read_exact(&mut header).await.unwrap();
+    assert_eq!(header[0], 5);
+    let address_size = if header[3] == 4 { 16 } else { 4 };
+    let mut bound = vec![0u8; address_size + 2];
+    client.read_exact(&mut bound).await.unwrap();
+    header[1]
+}
+
+/// send `payload`, shut down and return everything that comes back
+async fn exchange(mut client: TcpStream, payload: &[u8]) -> Vec<u8> {
+    client.write_all(payload).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut answer = Vec::new();
+    client.read_to_end(&mut answer).await.unwrap();
+    answer
+}
+
+#[tokio::test]
+async fn echoes_connections_to_the_echo_target() {
+    let (address, server) = start_server(&["--protocol", "socks5"]).await;
+
+    // by name, whatever the case
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0);
+    assert_eq!(request(&mut client, 1, "ECHO.invalid:7").await, 0);
+    assert_eq!(exchange(client, b"hello").await, b"hello");
+
+    // or by a configured address
+    let (address, _) =
+        start_server(&["--protocol", "socks5", "--socks-echo-target", "[::1]:9"]).await;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0);
+    assert_eq!(request(&mut client, 1, "[::1]:9").await, 0);
+    assert_eq!(exchange(client, b"world").await, b"world");
+
+    // accounted for like any session
+    for _ in 0..100 {
+        match server.stats.get(LOOPBACK) {
+            Some(stats) if stats.sessions == 1 => {
+                assert_eq!(stats.bytes, 5);
+                return;
+            }
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("session was not recorded");
+}
+
+#[tokio::test]
+async fn requires_a_known_password_when_keys_are_set() {
+    let (address, server) = start_server(&["--protocol", "socks5", "--auth-key", "secret"]).await;
+
+    // no authentication is not enough
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0xff);
+
+    // a wrong password is counted
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0, 2]).await, 2);
+    assert_eq!(log_in(&mut client, "harness", "guess").await, 1);
+    assert_eq!(server.auth.as_ref().unwrap().failures(LOOPBACK), 1);
+
+    // a key as password gets through
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0, 2]).await, 2);
+    assert_eq!(log_in(&mut client, "harness", "secret").await, 0);
+    assert_eq!(request(&mut client, 1, "echo.invalid:7").await, 0);
+    assert_eq!(exchange(client, b"hello").await, b"hello");
+}
+
+#[tokio::test]
+async fn relays_to_upstreams_only_when_allowed() {
+    // an upstream greeting its clients before echoing
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = upstream.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = upstream.accept().await.unwrap();
+            tokio::spawn(async move {
+                socket.write_all(b"upstream: ").await.unwrap();
+                let (mut reader, mut writer) = socket.split();
+                tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+            });
+        }
+    });
+
+    // refused by default
+    let (address, _) = start_server(&["--protocol", "socks5"]).await;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0);
+    assert_eq!(request(&mut client, 1, &target).await, 2);
+
+    // relayed both ways when allowed
+    let (address, _) = start_server(&["--protocol", "socks5", "--socks-upstream"]).await;
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0);
+    assert_eq!(request(&mut client, 1, &target).await, 0);
+    assert_eq!(exchange(client, b"hello").await, b"upstream: hello");
+
+    // refused upstream connections are reported as such
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_target = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0);
+    assert_eq!(request(&mut client, 1, &closed_target).await, 5);
+}
+
+#[tokio::test]
+async fn only_supports_connect() {
+    let (address, _) = start_server(&["--protocol", "socks5"]).await;
+
+    // BIND
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert_eq!(greet(&mut client, &[0]).await, 0);
+    assert_eq!(request(&mut client, 2, "echo.invalid:7").await, 7);
+}
+
+#[test]
+fn parses_targets() {
+    assert_eq!(
+        "127.0.0.1:80".parse::<Target>(),
+        Ok(Target::Address("127.0.0.1:80".parse().unwrap()))
+    );
+    assert_eq!(
+        "[::1]:80".parse::<Target>(),
+        Ok(Target::Address("[::1]:80".parse().unwrap()))
+    );
+    assert_eq!(
+        "echo.invalid:7".parse::<Target>(),
+        Ok(Target::Domain("echo.invalid".to_string(), 7))
+    );
+    assert!("no-port".parse::<Target>().is_err());
+    assert!("host:port".parse::<Target>().is_err());
+}
+
+#[test]
+fn socks_cannot_be_compressed() {
+    let config = Config::parse_from(["server", "--protocol", "socks5", "--compression"]);
+    assert!(Server::new(config).is_err());
+}
@@ -0,0 +1,102 @@
+// use tokio for async runtime
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// use clap to build options as if read from command line
+use clap::Parser;
+
+// types for addresses and timing
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use substrate_course_task_2::stats::{ClientStats, Stats};
+use substrate_course_task_2::{Config, Server};
+
+// starting servers, shared by the tests
+mod common;
+use common::start_server;
+
+/// loopback address the test clients come from
+const LOOPBACK: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+/// echo `payload` in one session
+async fn session(address: SocketAddr, payload: &[u8]) {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(payload).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed).await.unwrap();
+}
+
+/// wait until the server has accounted for `sessions` sessions of the loopback client
+async fn wait_for_sessions(server: &Server, sessions: u64) -> ClientStats {
+    for _ in 0..100 {
+        match server.stats.get(LOOPBACK) {
+            Some(stats) if stats.sessions >= sessions => return stats,
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("sessions were not recorded");
+}
+
+#[tokio::test]
+async fn aggregates_sessions_bytes_and_errors_per_client() {
+    let (address, server) = start_server(&["--auth-key", "secret"]).await;
+
+    // two good sessions
+    for payload in [&b"hello"[..], b"world!"] {
+        let mut client = TcpStream::connect(address).await.unwrap();
+        substrate_course_task_2::auth::authenticate(&mut client, b"secret")
+            .await
+            .unwrap();
+        client.write_all(payload).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await.unwrap();
+    }
+    // and one that fails
+    let mut client = TcpStream::connect(address).await.unwrap();
+    assert!(
+        substrate_course_task_2::auth::authenticate(&mut client, b"wrong")
+            .await
+            .is_err()
+    );
+
+    let stats = wait_for_sessions(&server, 3).await;
+    assert_eq!(stats.sessions, 3);
+    assert_eq!(stats.bytes, 11);
+    assert_eq!(stats.errors, 1);
+    assert!(stats.first_seen <= stats.last_seen);
+    assert!(stats.first_seen > 0);
+}
+
+#[tokio::test]
+async fn reloads_saved_statistics_at_startup() {
+    let path = std::env::temp_dir().join(format!("echo-stats-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // first run
+    let (address, server) = start_server(&["--stats-file", path.to_str().unwrap()]).await;
+    session(address, b"0123456789").await;
+    wait_for_sessions(&server, 1).await;
+    server.stats.save(&path).unwrap();
+
+    // second run picks up the same numbers
+    let (address, server) = start_server(&["--stats-file", path.to_str().unwrap()]).await;
+    assert_eq!(server.stats.get(LOOPBACK).unwrap().bytes, 10);
+    session(address, b"abc").await;
+    let stats = wait_for_sessions(&server, 2).await;
+    assert_eq!(stats.bytes, 13);
+
+    // what the stats subcommand reads
+    server.stats.save(&path).unwrap();
+    assert_eq!(Stats::load(&path).unwrap().get(LOOPBACK), Some(stats));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn refuses_a_zero_stats_interval() {
+    assert!(Config::try_parse_from(["server", "--stats-interval", "0"]).is_err());
+    assert!(Config::try_parse_from(["server", "--stats-interval", "1"]).is_ok());
+}
@@ -0,0 +1,22 @@
+// types for timing
+use std::time::Duration;
+
+use substrate_course_task_2::systemd::watchdog_interval;
+
+// the environment is shared by the whole process, so a single test changes it
+#[test]
+fn pings_the_watchdog_twice_per_timeout_unless_disabled() {
+    // meant for this process
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+    std::env::set_var("WATCHDOG_USEC", "3000000");
+    assert_eq!(watchdog_interval(), Some(Duration::from_millis(1500)));
+
+    // a zero timeout turns the watchdog off
+    std::env::set_var("WATCHDOG_USEC", "0");
+    assert_eq!(watchdog_interval(), None);
+
+    // meant for another process
+    std::env::set_var("WATCHDOG_USEC", "3000000");
+    std::env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(watchdog_interval(), None);
+}